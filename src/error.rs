@@ -159,4 +159,32 @@ error_enums!(
     (DUPLICATE_STEP,9,"DUPLICATE_STEP");
     (EVENT_BUS_DUPLICATE_CLIENTID,10,"duplicate client id");
     (EVENT_BUS_SUBSCRIBE_FAILED,11,"failed to subscribe");
+    (WAL_CORRUPTED,12,"wal record corrupted");
+    (WAL_NOT_RECOVERED,13,"wal has pending records,recover first");
+    (WAL_TREE_NOT_FOUND,14,"wal references an unknown tree");
+    (ATOMIC_COMMIT_FAILED,15,"atomic commit failed");
+    (MERKLE_OPEN_FAILED,16,"failed to open merkle db");
+    (MERKLE_CORRUPTED_NODE,17,"merkle node corrupted");
+    (SMT_INVALID_PROOF,18,"invalid sparse merkle proof");
+    (TREE_BUILDER_INVALID,19,"invalid tree builder option");
+    (MIDDLEWARE_CHAIN_INVALID,20,"invalid middleware chain");
+    (AUDIT_LOG_CORRUPTED,21,"audit log corrupted");
+    (NAMESPACE_UNKNOWN,22,"unknown namespace");
+    (NAMESPACE_READ_FORBIDDEN,23,"namespace read forbidden");
+    (NAMESPACE_WRITE_FORBIDDEN,24,"namespace write forbidden");
+    (KEY_TOO_LONG,25,"key too long");
+    (VALUE_TOO_LONG,26,"value too long");
+    (VALUE_INVALID,27,"value rejected by validator");
+    (READ_ONLY,28,"tree is read-only");
+    (PREFIXED_TREE_INVALID,30,"invalid prefixed sub-tree");
+    (PRECONDITION_FAILED,31,"precondition failed");
+    (OPERATION_INVALID,32,"invalid operation");
+    (ARITHMETIC_OVERFLOW,33,"arithmetic overflow");
+    (CODEC_INVALID,34,"malformed encoded message");
+    (MERK_PROOF_INVALID,35,"malformed merk proof");
+    (STATE_SYNC_FAILED,36,"state sync failed");
+    (DUMP_CORRUPTED,37,"state dump corrupted");
+    (ROOT_MISMATCH,38,"root hash mismatch");
+    (GENESIS_INVALID,39,"invalid genesis config");
+    (NAMESPACE_COMMIT_FORBIDDEN,40,"namespace commit forbidden");
 );
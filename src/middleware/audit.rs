@@ -0,0 +1,526 @@
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_keccak::{Hasher, Keccak};
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::middleware::middleware::TreeMiddleware;
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::operation::{resolve, Operation};
+use crate::tree::tree::{DB, TreeDB};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditKind {
+    Set,
+    Delete,
+    /// written and synced before the tree commits, `old` holds the root hash before the commit
+    Prepare,
+    /// `old`/`new` hold the root hash before and after the commit
+    Commit,
+    /// the prepared commit did not land, `old` holds the root hash it was prepared on
+    Abort,
+}
+
+impl AuditKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuditKind::Set => "set",
+            AuditKind::Delete => "delete",
+            AuditKind::Prepare => "prepare",
+            AuditKind::Commit => "commit",
+            AuditKind::Abort => "abort",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "set" => Some(AuditKind::Set),
+            "delete" => Some(AuditKind::Delete),
+            "prepare" => Some(AuditKind::Prepare),
+            "commit" => Some(AuditKind::Commit),
+            "abort" => Some(AuditKind::Abort),
+            _ => None,
+        }
+    }
+}
+
+/// one line of the audit log:
+/// `seq|version|timestamp_ms|kind|key|old|new|prev_hash|hash`, bytes in hex and `-` for none.
+/// `hash` is keccak256 over `prev_hash` and every field before it, chaining each line to the previous one
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub version: u64,
+    pub timestamp_ms: u64,
+    pub kind: AuditKind,
+    pub key: Vec<u8>,
+    pub old: Option<Vec<u8>>,
+    pub new: Option<Vec<u8>>,
+    pub prev_hash: [u8; 32],
+    pub hash: [u8; 32],
+}
+
+fn encode_opt(v: &Option<Vec<u8>>) -> String {
+    v.as_ref().map_or("-".to_string(), hex::encode)
+}
+
+impl AuditEntry {
+    fn body(&self) -> String {
+        format!("{}|{}|{}|{}|{}|{}|{}", self.seq, self.version, self.timestamp_ms, self.kind.as_str(),
+                hex::encode(&self.key), encode_opt(&self.old), encode_opt(&self.new))
+    }
+
+    pub fn compute_hash(&self) -> [u8; 32] {
+        let mut keccak = Keccak::v256();
+        keccak.update(&self.prev_hash);
+        keccak.update(self.body().as_bytes());
+        let mut out = [0u8; 32];
+        keccak.finalize(&mut out);
+        out
+    }
+
+    fn to_line(&self) -> String {
+        format!("{}|{}|{}\n", self.body(), hex::encode(self.prev_hash), hex::encode(self.hash))
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split('|').collect();
+        if fields.len() != 9 {
+            return None;
+        }
+        let bytes = |s: &str| hex::decode(s).ok();
+        let opt = |s: &str| if s == "-" { Some(None) } else { bytes(s).map(Some) };
+        let hash = |s: &str| bytes(s).filter(|v| v.len() == 32).map(|v| {
+            let mut ret = [0u8; 32];
+            ret.copy_from_slice(v.as_slice());
+            ret
+        });
+        Some(AuditEntry {
+            seq: fields[0].parse().ok()?,
+            version: fields[1].parse().ok()?,
+            timestamp_ms: fields[2].parse().ok()?,
+            kind: AuditKind::parse(fields[3])?,
+            key: bytes(fields[4])?,
+            old: opt(fields[5])?,
+            new: opt(fields[6])?,
+            prev_hash: hash(fields[7])?,
+            hash: hash(fields[8])?,
+        })
+    }
+}
+
+/// append-only, hash chained log file
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    seq: u64,
+    version: u64,
+    last_hash: [u8; 32],
+    /// length of the complete lines, a failed write is cut back to it
+    len: u64,
+    /// root hash of a prepare not followed by its commit or abort yet
+    pending: Option<Vec<u8>>,
+}
+
+impl AuditLog {
+    /// opens or creates the log, an existing log must verify before new entries are appended.
+    /// a final line without its newline was torn by a crash while being written and is cut off,
+    /// it never belonged to a synced commit
+    pub fn open<P: AsRef<Path>>(p: P) -> ZKResult<Self> {
+        let io_error = |e: std::io::Error| ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e));
+        let path = p.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path).map_err(io_error)?;
+        let content = std::fs::read(&path).map_err(io_error)?;
+        let len = content.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1) as u64;
+        if len != content.len() as u64 {
+            file.set_len(len).and_then(|_| file.sync_data()).map_err(io_error)?;
+        }
+        let entries = Self::verify(&path)?;
+        let pending = entries.last().filter(|e| e.kind == AuditKind::Prepare).and_then(|e| e.old.clone());
+        let (seq, version, last_hash) = match entries.last() {
+            None => (0, 0, [0u8; 32]),
+            Some(e) if e.kind == AuditKind::Commit => (e.seq + 1, e.version, e.hash),
+            Some(e) => {
+                let version = e.version.checked_sub(1).ok_or_else(|| {
+                    ZKError::new(ErrorEnumsStruct::AUDIT_LOG_CORRUPTED.get_code(), format!("entry {} has version 0", e.seq))
+                })?;
+                (e.seq + 1, version, e.hash)
+            }
+        };
+        Ok(Self { path, file, seq, version, last_hash, len, pending })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// number of commits recorded so far
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// root hash of the last prepare when the log ends before its commit or abort
+    pub fn pending(&self) -> Option<&[u8]> {
+        self.pending.as_deref()
+    }
+
+    /// reads the whole log and checks every line links to the previous one and every commit or
+    /// abort settles the prepare right before it. needs nothing but the file so it can run offline,
+    /// a final prepare is a commit whose outcome was not recorded yet, see `pending`
+    pub fn verify<P: AsRef<Path>>(p: P) -> ZKResult<Vec<AuditEntry>> {
+        let corrupted = |line: usize, reason: &str| {
+            ZKError::new(ErrorEnumsStruct::AUDIT_LOG_CORRUPTED.get_code(), format!("line {}: {}", line + 1, reason))
+        };
+        let file = File::open(p).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e))
+        })?;
+        let mut ret: Vec<AuditEntry> = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| {
+                ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e))
+            })?;
+            let entry = AuditEntry::parse(line.as_str()).ok_or_else(|| corrupted(i, "malformed entry"))?;
+            let (prev_hash, seq) = ret.last().map_or(([0u8; 32], 0), |e| (e.hash, e.seq + 1));
+            if entry.prev_hash != prev_hash || entry.seq != seq {
+                return Err(corrupted(i, "broken chain"));
+            }
+            if entry.compute_hash() != entry.hash {
+                return Err(corrupted(i, "hash mismatch"));
+            }
+            let prepare = ret.last().filter(|e| e.kind == AuditKind::Prepare);
+            match entry.kind {
+                AuditKind::Commit | AuditKind::Abort if prepare.is_none_or(|p| p.old != entry.old) => {
+                    return Err(corrupted(i, "not settling a prepare"));
+                }
+                AuditKind::Commit | AuditKind::Abort => {}
+                _ if prepare.is_some() => return Err(corrupted(i, "prepare not settled")),
+                _ => {}
+            }
+            ret.push(entry);
+        }
+        Ok(ret)
+    }
+
+    fn append(&mut self, kind: AuditKind, key: Vec<u8>, old: Option<Vec<u8>>, new: Option<Vec<u8>>) -> ZKResult<()> {
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        let mut entry = AuditEntry {
+            seq: self.seq,
+            version: self.version + 1,
+            timestamp_ms,
+            kind,
+            key,
+            old,
+            new,
+            prev_hash: self.last_hash,
+            hash: [0u8; 32],
+        };
+        entry.hash = entry.compute_hash();
+        let line = entry.to_line();
+        let written = self.file.write_all(line.as_bytes()).and_then(|_| {
+            match kind {
+                AuditKind::Set | AuditKind::Delete => Ok(()),
+                _ => self.file.sync_data(),
+            }
+        });
+        if let Err(e) = written {
+            // best effort, a torn line left behind is cut off by `open`
+            let _ = self.file.set_len(self.len);
+            return Err(ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e)));
+        }
+        self.seq += 1;
+        self.last_hash = entry.hash;
+        self.len += line.len() as u64;
+        match kind {
+            AuditKind::Prepare => self.pending = entry.old,
+            AuditKind::Commit => {
+                self.pending = None;
+                self.version += 1;
+            }
+            AuditKind::Abort => self.pending = None,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// records every mutation passing through it, entries carry the version they will be committed in
+pub struct AuditMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    inner: M,
+    log: AuditLog,
+}
+
+impl<M> AuditMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    pub fn new(inner: M, log: AuditLog) -> Self {
+        Self { inner, log }
+    }
+
+    pub fn log(&self) -> &AuditLog {
+        &self.log
+    }
+
+    /// records the outcome of a prepare the log ends with, left by a crash or a record that
+    /// could not be written. the tree still at the prepared root means the commit did not land,
+    /// which also files a commit that changed nothing as aborted
+    fn settle(&mut self) -> ZKResult<()> {
+        let old_root = match self.log.pending.clone() {
+            Some(root) => root,
+            None => return Ok(()),
+        };
+        let root = self.inner.root_hash();
+        match root[..] == old_root[..] {
+            true => self.log.append(AuditKind::Abort, vec![], Some(old_root), None),
+            false => self.log.append(AuditKind::Commit, vec![], Some(old_root), Some(root.to_vec())),
+        }
+    }
+}
+
+impl<M> DB for AuditMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        self.inner.get(k)
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        self.settle()?;
+        let old = self.inner.set(k, v.clone())?;
+        self.log.append(AuditKind::Set, k.to_vec(), old.clone(), Some(v))?;
+        Ok(old)
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        self.settle()?;
+        let old = self.inner.delete(k)?;
+        self.log.append(AuditKind::Delete, k.to_vec(), old.clone(), None)?;
+        Ok(old)
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        self.inner.get_many(keys)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.range(start, end)
+    }
+}
+
+impl<M> TreeDB for AuditMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        self.inner.prove(req)
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        self.inner.verify(req)
+    }
+
+    /// operations handed to commit directly are resolved to sets and deletes and logged one by one,
+    /// followed by a synced prepare, all before the tree commits. the commit or abort record is
+    /// written afterwards, when that fails the prepare is settled by the next write
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        self.settle()?;
+        let operations = resolve(&self.inner, operations)?;
+        let mut entries = Vec::with_capacity(operations.len());
+        for op in operations.iter() {
+            match op {
+                Operation::Set(k, v) => entries.push((AuditKind::Set, k.clone(), self.inner.get(k)?.map(Cow::into_owned), Some(v.clone()))),
+                Operation::Delete(k) => entries.push((AuditKind::Delete, k.clone(), self.inner.get(k)?.map(Cow::into_owned), None)),
+                _ => unreachable!("resolve only emits sets and deletes"),
+            }
+        }
+        let old_root = self.inner.root_hash();
+        for (kind, k, old, new) in entries {
+            self.log.append(kind, k, old, new)?;
+        }
+        self.log.append(AuditKind::Prepare, vec![], Some(old_root.to_vec()), None)?;
+        if let Err(e) = self.inner.commit(operations) {
+            let _ = self.settle();
+            return Err(e);
+        }
+        let new_root = self.inner.root_hash();
+        let _ = self.log.append(AuditKind::Commit, vec![], Some(old_root.to_vec()), Some(new_root.to_vec()));
+        Ok(())
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.inner.root_hash()
+    }
+
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        self.inner.prepare(operations)
+    }
+}
+
+impl<M> TreeMiddleware for AuditMiddleware<M>
+    where
+        M: TreeMiddleware + 'static,
+{
+    fn name(&self) -> &'static str {
+        "audit"
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        Some(&self.inner)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        Some(&mut self.inner)
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        Some(Box::new(self.inner))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::Write;
+    use crate::error::ErrorEnumsStruct;
+    use crate::middleware::audit::{AuditEntry, AuditKind, AuditLog, AuditMiddleware};
+    use crate::middleware::cache::CacheMiddleware;
+    use crate::middleware::middleware::DBMiddleware;
+    use crate::middleware::validate::{ValidationConfig, ValidationMiddleware};
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::operation::Operation;
+    use crate::tree::tree::{DB, TreeDB};
+
+    #[test]
+    pub fn test_audit_chain() {
+        let path = std::env::temp_dir().join(format!("zkp_audit_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let cache = CacheMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()));
+        let mut audit = AuditMiddleware::new(cache, AuditLog::open(&path).expect("fail to open"));
+        audit.set(&[1], vec![10]).unwrap();
+        audit.set(&[1], vec![11]).unwrap();
+        audit.delete(&[2]).unwrap();
+        audit.commit(vec![Operation::Set(vec![3], vec![30])]).unwrap();
+        assert_eq!(audit.log().version(), 1);
+
+        let entries = AuditLog::verify(&path).expect("fail to verify");
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entries[1].old, Some(vec![10]));
+        assert_eq!(entries[1].new, Some(vec![11]));
+        assert_eq!(entries[4].kind, AuditKind::Prepare);
+        assert_eq!(entries[5].kind, AuditKind::Commit);
+        assert_eq!(entries[5].new, Some(audit.root_hash().to_vec()));
+
+        let reopened = AuditLog::open(&path).expect("fail to reopen");
+        assert_eq!(reopened.version(), 1);
+
+        let content = fs::read_to_string(&path).unwrap().replacen("|0b|", "|0c|", 1);
+        fs::write(&path, content).unwrap();
+        assert!(AuditLog::verify(&path).is_err());
+    }
+
+    #[test]
+    pub fn test_open_recovers_torn_line() {
+        let path = std::env::temp_dir().join(format!("zkp_audit_torn_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut log = AuditLog::open(&path).expect("fail to open");
+        log.append(AuditKind::Set, vec![1], None, Some(vec![1])).unwrap();
+        log.append(AuditKind::Prepare, vec![], None, None).unwrap();
+        log.append(AuditKind::Commit, vec![], None, Some(vec![0; 32])).unwrap();
+        drop(log);
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"3|2|17000|set|02").unwrap();
+        drop(file);
+
+        let mut log = AuditLog::open(&path).expect("fail to recover");
+        assert_eq!(log.version(), 1);
+        log.append(AuditKind::Set, vec![2], None, Some(vec![2])).unwrap();
+        assert_eq!(AuditLog::verify(&path).expect("fail to verify").len(), 4);
+    }
+
+    #[test]
+    pub fn test_settles_pending_prepare() {
+        let path = std::env::temp_dir().join(format!("zkp_audit_pending_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut db = DBMiddleware::new(MemoryTreeDB::new());
+        let empty = db.root_hash().to_vec();
+
+        // crashed before the tree committed
+        let mut log = AuditLog::open(&path).expect("fail to open");
+        log.append(AuditKind::Set, vec![1], None, Some(vec![1])).unwrap();
+        log.append(AuditKind::Prepare, vec![], Some(empty.clone()), None).unwrap();
+        drop(log);
+        let log = AuditLog::open(&path).expect("fail to reopen");
+        assert_eq!(log.pending(), Some(&empty[..]));
+        assert_eq!(log.version(), 0);
+        let mut audit = AuditMiddleware::new(db, log);
+        audit.commit(vec![Operation::Set(vec![2], vec![2])]).expect("fail to commit");
+        let entries = AuditLog::verify(&path).expect("fail to verify");
+        assert_eq!(entries[2].kind, AuditKind::Abort);
+        assert_eq!(entries.last().unwrap().kind, AuditKind::Commit);
+        assert_eq!(audit.log().version(), 1);
+
+        // crashed after the tree committed
+        db = DBMiddleware::new(MemoryTreeDB::new());
+        let mut log = AuditLog::open(&path).expect("fail to reopen");
+        log.append(AuditKind::Prepare, vec![], Some(db.root_hash().to_vec()), None).unwrap();
+        db.commit(vec![Operation::Set(vec![3], vec![3])]).unwrap();
+        let mut audit = AuditMiddleware::new(db, log);
+        audit.set(&[4], vec![4]).expect("fail to set");
+        let entries = AuditLog::verify(&path).expect("fail to verify");
+        let settled = &entries[entries.len() - 2];
+        assert_eq!(settled.kind, AuditKind::Commit);
+        assert_eq!(settled.old, Some(empty));
+        assert_eq!(audit.log().version(), 2);
+        assert!(audit.log().pending().is_none());
+    }
+
+    #[test]
+    pub fn test_failed_commit_is_aborted() {
+        let path = std::env::temp_dir().join(format!("zkp_audit_abort_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let strict = ValidationMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()), ValidationConfig::new(8, 1));
+        let mut audit = AuditMiddleware::new(strict, AuditLog::open(&path).expect("fail to open"));
+        assert!(audit.commit(vec![Operation::Set(vec![1], vec![1, 1])]).is_err());
+        audit.commit(vec![Operation::Set(vec![1], vec![1])]).expect("fail to commit");
+
+        let kinds: Vec<AuditKind> = AuditLog::verify(&path).expect("fail to verify").into_iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![AuditKind::Set, AuditKind::Prepare, AuditKind::Abort,
+                               AuditKind::Set, AuditKind::Prepare, AuditKind::Commit]);
+        assert_eq!(audit.log().version(), 1);
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        let mut entry = AuditEntry::parse(fs::read_to_string(&path).unwrap().lines().last().unwrap()).unwrap();
+        entry.prev_hash = entry.hash;
+        entry.seq += 1;
+        entry.hash = entry.compute_hash();
+        file.write_all(entry.to_line().as_bytes()).unwrap();
+        let err = AuditLog::verify(&path).expect_err("commit without prepare is refused");
+        assert_eq!(err.get_code(), ErrorEnumsStruct::AUDIT_LOG_CORRUPTED.get_code());
+    }
+
+    #[test]
+    pub fn test_open_rejects_version_zero() {
+        let path = std::env::temp_dir().join(format!("zkp_audit_zero_{}", std::process::id()));
+        let mut entry = AuditEntry {
+            seq: 0,
+            version: 0,
+            timestamp_ms: 0,
+            kind: AuditKind::Set,
+            key: vec![1],
+            old: None,
+            new: Some(vec![1]),
+            prev_hash: [0; 32],
+            hash: [0; 32],
+        };
+        entry.hash = entry.compute_hash();
+        fs::write(&path, entry.to_line()).unwrap();
+        let err = AuditLog::open(&path).err().expect("version 0 is refused");
+        assert_eq!(err.get_code(), ErrorEnumsStruct::AUDIT_LOG_CORRUPTED.get_code());
+    }
+}
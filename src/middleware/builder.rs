@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::middleware::chain::MiddlewareChain;
+use crate::middleware::middleware::{DBMiddleware, DBType, MiddlewareType, TreeMiddleware};
+use crate::tree::hasher::{Blake2bHasher, KeccakHasher, PoseidonHasher, TreeHasher};
+use crate::tree::memory::MemoryTreeDB;
+use crate::tree::merkle::MerkleRocksDB;
+use crate::tree::smt::SMTreeDB;
+use crate::tree::tree::TreeDB;
+
+/// hashers selectable at runtime, merk based backends always use merk's own hash
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HasherType {
+    Keccak,
+    Blake2b,
+    Poseidon,
+}
+
+/// builds a backend and stacks the configured middlewares on top of it:
+/// `Merk::open` -> `MerkleRocksDB` -> `DBMiddleware` -> `CacheMiddleware` -> ...
+pub struct TreeBuilder {
+    db_type: DBType,
+    path: Option<PathBuf>,
+    hasher: Option<HasherType>,
+    middlewares: Vec<MiddlewareType>,
+}
+
+impl TreeBuilder {
+    pub fn new(db_type: DBType) -> Self {
+        Self { db_type, path: None, hasher: None, middlewares: vec![] }
+    }
+
+    /// required by `DBType::Merkle`, optional for `DBType::SMT` which then persists its leaves
+    pub fn path<P: AsRef<Path>>(mut self, p: P) -> Self {
+        self.path = Some(p.as_ref().to_path_buf());
+        self
+    }
+
+    /// only `DBType::SMT` accepts a hasher, defaults to keccak
+    pub fn hasher(mut self, hasher: HasherType) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// middlewares wrap each other in the order they are added
+    pub fn middleware(mut self, m: MiddlewareType) -> Self {
+        self.middlewares.push(m);
+        self
+    }
+
+    pub fn build(self) -> ZKResult<Box<dyn TreeMiddleware>> {
+        let mut top: Box<dyn TreeMiddleware> = Box::new(DBMiddleware::new(self.backend()?));
+        for m in self.middlewares.iter() {
+            top = m.wrap(top)?;
+        }
+        Ok(top)
+    }
+
+    /// same stack as `build`, but its layers can be reconfigured afterwards
+    pub fn build_chain(self) -> ZKResult<MiddlewareChain> {
+        let bottom = Box::new(DBMiddleware::new(self.backend()?));
+        MiddlewareChain::new(bottom, self.middlewares)
+    }
+
+    fn backend(&self) -> ZKResult<Box<dyn TreeDB>> {
+        if self.db_type != DBType::SMT && self.hasher.is_some() {
+            return Err(ZKError::new(ErrorEnumsStruct::TREE_BUILDER_INVALID.get_code(),
+                                    format!("{:?} does not support a custom hasher", self.db_type)));
+        }
+        match self.db_type {
+            DBType::Merkle => {
+                let path = self.path.as_ref().ok_or_else(|| {
+                    ZKError::new(ErrorEnumsStruct::TREE_BUILDER_INVALID.get_code(), "merkle requires a path".to_string())
+                })?;
+                Ok(Box::new(MerkleRocksDB::new_with_path(path)?))
+            }
+            DBType::InMemory => Ok(Box::new(MemoryTreeDB::new())),
+            DBType::SMT => match self.hasher.unwrap_or(HasherType::Keccak) {
+                HasherType::Keccak => self.smt::<KeccakHasher>(),
+                HasherType::Blake2b => self.smt::<Blake2bHasher>(),
+                HasherType::Poseidon => self.smt::<PoseidonHasher>(),
+            },
+        }
+    }
+
+    fn smt<H: TreeHasher>(&self) -> ZKResult<Box<dyn TreeDB>> {
+        match self.path.as_ref() {
+            Some(p) => Ok(Box::new(SMTreeDB::<H>::new_with_path(p)?)),
+            None => Ok(Box::new(SMTreeDB::<H>::new())),
+        }
+    }
+}
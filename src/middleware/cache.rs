@@ -1,9 +1,11 @@
+use std::borrow::Cow;
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use crate::error::ZKResult;
-use crate::middleware::middleware::TreeMiddleware;
+use crate::middleware::middleware::{CacheStats, TreeMiddleware};
 use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
-use crate::tree::operation::Operation;
-use crate::tree::tree::{DB, TreeDB};
+use crate::tree::operation::{resolve, Operation};
+use crate::tree::tree::{range_bounds, DB, TreeDB};
 
 type Map = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
 
@@ -15,6 +17,8 @@ pub struct CacheMiddleware<M>
     map: Option<Map>,
     inner: M,
     version: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 // #[derive(Default)]
@@ -54,38 +58,113 @@ impl<M> CacheMiddleware<M>
             map: Some(Default::default()),
             inner: mid,
             version: 0,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         }
     }
+
+    /// the key is only copied the first time it is written, the old value comes from the
+    /// overlay when the key was written before and from the layers below otherwise
+    fn write(&mut self, k: &[u8], v: Option<Vec<u8>>) -> ZKResult<Option<Vec<u8>>> {
+        if let Some(slot) = self.map.as_mut().unwrap().get_mut(k) {
+            return Ok(std::mem::replace(slot, v));
+        }
+        let old = self.inner.get(k)?.map(Cow::into_owned);
+        self.map.as_mut().unwrap().insert(k.to_vec(), v);
+        Ok(old)
+    }
+
+    /// `operations` go first and the overlay after them, so a buffered write wins over a write
+    /// passed to `commit`. increments and merges among `operations` are resolved against the
+    /// layers below, as if they had been applied before anything was buffered
+    fn drain(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        let mut ret = resolve(&self.inner, operations)?;
+        let map = self.map.replace(Map::new()).unwrap();
+        for (k, v) in map {
+            match v {
+                Some(value) => ret.push(Operation::Set(k, value)),
+                None => ret.push(Operation::Delete(k)),
+            }
+        }
+        Ok(ret)
+    }
 }
 
 impl<M> DB for CacheMiddleware<M>
     where
         M: TreeMiddleware,
 {
-    fn get(&self, k: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
-        match self.map.as_ref().unwrap().get(k.as_slice()) {
-            Some(Some(value)) => Ok(Some(value.clone())),
-            Some(None) => Ok(None),
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        match self.map.as_ref().unwrap().get(k) {
+            Some(value) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(value.as_deref().map(Cow::Borrowed))
+            }
             None => {
-                self.inner.get(k.clone()).map(|v| {
-                    if let Some(value) = v {
-                        Some(value)
-                    } else {
-                        None
-                    }
-                })
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                self.inner.get(k)
             }
         }
     }
 
-    fn set(&mut self, k: Vec<u8>, v: Vec<u8>) -> ZKResult<Vec<u8>> {
-        self.map.as_mut().unwrap().insert(k.clone(), Some(v));
-        Ok(k.clone())
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        self.write(k, Some(v))
     }
 
 
-    fn delete(&mut self, k: Vec<u8>) -> ZKResult<()> {
-        self.map.as_mut().unwrap().insert(k, None);
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        self.write(k, None)
+    }
+
+    /// answers from the overlay first, the misses go down in a single `get_many`
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        let map = self.map.as_ref().unwrap();
+        let mut ret = Vec::with_capacity(keys.len());
+        let mut missed = Vec::new();
+        for (i, k) in keys.iter().enumerate() {
+            match map.get(*k) {
+                Some(v) => ret.push(v.clone()),
+                None => {
+                    missed.push(i);
+                    ret.push(None);
+                }
+            }
+        }
+        self.hits.fetch_add((keys.len() - missed.len()) as u64, Ordering::Relaxed);
+        self.misses.fetch_add(missed.len() as u64, Ordering::Relaxed);
+        if missed.is_empty() {
+            return Ok(ret);
+        }
+        let query: Vec<&[u8]> = missed.iter().map(|i| keys[*i]).collect();
+        for (i, v) in missed.into_iter().zip(self.inner.get_many(query.as_slice())?) {
+            ret[i] = v;
+        }
+        Ok(ret)
+    }
+
+    /// the overlay is laid over the range read below, buffered deletes hide the keys
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut ret: BTreeMap<Vec<u8>, Vec<u8>> = self.inner.range(start, end)?.into_iter().collect();
+        if let Some(bounds) = range_bounds(start, end) {
+            for (k, v) in self.map.as_ref().unwrap().range::<[u8], _>(bounds) {
+                match v {
+                    Some(v) => ret.insert(k.clone(), v.clone()),
+                    None => ret.remove(k),
+                };
+            }
+        }
+        Ok(ret.into_iter().collect())
+    }
+
+    /// resolved against the overlay and buffered like plain writes, nothing reaches the layers below
+    fn write_batch(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        for op in resolve(self, operations)? {
+            match op {
+                Operation::Set(k, v) => self.map.as_mut().unwrap().insert(k, Some(v)),
+                Operation::Delete(k) => self.map.as_mut().unwrap().insert(k, None),
+                _ => unreachable!("resolve only emits sets and deletes"),
+            };
+        }
         Ok(())
     }
 }
@@ -101,37 +180,51 @@ impl<M> TreeDB for CacheMiddleware<M>
         self.inner.verify(req)
     }
 
-    fn commit(&mut self, mut operations: Vec<Operation>) -> ZKResult<()> {
-        let map = self.map.take().unwrap();
-        self.map = Some(Map::new());
-
-        for (k, v) in map {
-            match v.clone() {
-                Some(value) => operations.push(Operation::Set(k, value)),
-                None => operations.push(Operation::Delete(k)),
-            }
-        }
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        let operations = self.drain(operations)?;
         self.inner.commit(operations)
     }
 
     fn root_hash(&self) -> [u8; 32] {
         self.inner.root_hash()
     }
+
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        let operations = self.drain(operations)?;
+        self.inner.prepare(operations)
+    }
 }
 
 
 impl<M> TreeMiddleware for CacheMiddleware<M>
     where
-        M: TreeMiddleware,
+        M: TreeMiddleware + 'static,
 {
-    type Inner = M;
+    fn name(&self) -> &'static str {
+        "cache"
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        Some(&self.inner)
+    }
 
-    fn inner(&self) -> &Self::Inner {
-        &self.inner
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        Some(&mut self.inner)
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        Some(Box::new(self.inner))
     }
 
     fn clean(&mut self) -> ZKResult<()> {
         self.map = Some(Map::new());
         Ok(())
     }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        })
+    }
 }
\ No newline at end of file
@@ -0,0 +1,323 @@
+use std::borrow::Cow;
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::middleware::audit::{AuditLog, AuditMiddleware};
+use crate::middleware::cache::CacheMiddleware;
+use crate::middleware::lru::ReadCacheMiddleware;
+use crate::middleware::merge::MergeMiddleware;
+use crate::middleware::metrics::MetricsMiddleware;
+use crate::middleware::middleware::{MiddlewareType, TreeMiddleware};
+use crate::middleware::validate::ValidationMiddleware;
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::operation::{Operation, Precondition};
+use crate::tree::tree::{DB, TreeDB};
+
+impl MiddlewareType {
+    pub fn wrap(&self, inner: Box<dyn TreeMiddleware>) -> ZKResult<Box<dyn TreeMiddleware>> {
+        let log = self.acquire()?;
+        Ok(self.stack(inner, log))
+    }
+
+    /// everything a layer can fail to get, taken before the layer below is handed over
+    fn acquire(&self) -> ZKResult<Option<AuditLog>> {
+        match self {
+            MiddlewareType::Audit(p) => AuditLog::open(p).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn stack(&self, inner: Box<dyn TreeMiddleware>, log: Option<AuditLog>) -> Box<dyn TreeMiddleware> {
+        match self {
+            MiddlewareType::Cache => Box::new(CacheMiddleware::new(inner)),
+            MiddlewareType::Metrics(m) => Box::new(MetricsMiddleware::new(inner, m.metrics())),
+            MiddlewareType::Audit(_) => Box::new(AuditMiddleware::new(inner, log.expect("audit log is acquired first"))),
+            MiddlewareType::ReadCache(c) => Box::new(ReadCacheMiddleware::new(inner, *c)),
+            MiddlewareType::Validation(c) => Box::new(ValidationMiddleware::new(inner, c.clone())),
+            MiddlewareType::Merge(o) => Box::new(MergeMiddleware::new(inner, o.clone())),
+        }
+    }
+}
+
+fn acquire_all(layers: &[MiddlewareType]) -> ZKResult<Vec<Option<AuditLog>>> {
+    layers.iter().map(|l| l.acquire()).collect()
+}
+
+fn stack_all(mut top: Box<dyn TreeMiddleware>, layers: &[MiddlewareType], logs: Vec<Option<AuditLog>>) -> Box<dyn TreeMiddleware> {
+    for (l, log) in layers.iter().zip(logs) {
+        top = l.stack(top, log);
+    }
+    top
+}
+
+/// peels `count` layers, only called on stacks built by `stack_all`
+fn unstack(mut top: Box<dyn TreeMiddleware>, count: usize) -> Box<dyn TreeMiddleware> {
+    for _ in 0..count {
+        top = top.into_inner().expect("every configured layer has an inner layer");
+    }
+    top
+}
+
+/// a stack of layers over a fixed bottom, the layers can be pushed, removed or reordered at runtime.
+/// writes still buffered in the stack are drained before it is rebuilt and replayed on top of the
+/// new stack, so reconfiguring never loses uncommitted state. dropping the last cache layer while
+/// writes are buffered is refused, the replay would write them through to the bottom outside of a commit
+pub struct MiddlewareChain {
+    top: Option<Box<dyn TreeMiddleware>>,
+    layers: Vec<MiddlewareType>,
+}
+
+impl MiddlewareChain {
+    pub fn new(bottom: Box<dyn TreeMiddleware>, layers: Vec<MiddlewareType>) -> ZKResult<Self> {
+        let mut top = bottom;
+        for l in layers.iter() {
+            top = l.wrap(top)?;
+        }
+        Ok(Self { top: Some(top), layers })
+    }
+
+    pub fn layers(&self) -> &[MiddlewareType] {
+        self.layers.as_slice()
+    }
+
+    pub fn push(&mut self, layer: MiddlewareType) -> ZKResult<()> {
+        let mut layers = self.layers.clone();
+        layers.push(layer);
+        self.reconfigure(layers)
+    }
+
+    pub fn remove(&mut self, index: usize) -> ZKResult<MiddlewareType> {
+        if index >= self.layers.len() {
+            return Err(ZKError::new(ErrorEnumsStruct::MIDDLEWARE_CHAIN_INVALID.get_code(),
+                                    format!("no layer at {}", index)));
+        }
+        let mut layers = self.layers.clone();
+        let ret = layers.remove(index);
+        self.reconfigure(layers)?;
+        Ok(ret)
+    }
+
+    /// rebuilds the chain with `layers` from the bottom up. on error the chain keeps its old
+    /// layers and the buffered writes. only when the new layers reject the buffered writes and
+    /// the old ones can not be reopened, the chain is left with its bottom alone and the writes are lost
+    pub fn reconfigure(&mut self, layers: Vec<MiddlewareType>) -> ZKResult<()> {
+        let mut top = self.top.take().ok_or_else(|| ZKError::from(ErrorEnumsStruct::MIDDLEWARE_CHAIN_INVALID))?;
+        let pending = match top.prepare(vec![]) {
+            Ok(ops) => ops,
+            Err(e) => {
+                self.top = Some(top);
+                return Err(e);
+            }
+        };
+        let checked = self.check_depth(top.as_ref()).and_then(|_| self.check_buffering(&pending, layers.as_slice()));
+        let logs = match checked.and_then(|_| acquire_all(layers.as_slice())) {
+            Ok(logs) => logs,
+            Err(e) => {
+                let restored = top.write_batch(pending);
+                self.top = Some(top);
+                return restored.and(Err(e));
+            }
+        };
+
+        let mut top = stack_all(unstack(top, self.layers.len()), layers.as_slice(), logs);
+        if let Err(e) = top.write_batch(pending.clone()) {
+            let bottom = unstack(top, layers.len());
+            match acquire_all(self.layers.as_slice()) {
+                Ok(logs) => {
+                    let mut old = stack_all(bottom, self.layers.as_slice(), logs);
+                    let restored = old.write_batch(pending);
+                    self.top = Some(old);
+                    return restored.and(Err(e));
+                }
+                Err(_) => {
+                    self.top = Some(bottom);
+                    self.layers = vec![];
+                    return Err(e);
+                }
+            }
+        }
+        self.top = Some(top);
+        self.layers = layers;
+        Ok(())
+    }
+
+    fn check_depth(&self, top: &dyn TreeMiddleware) -> ZKResult<()> {
+        let mut cur = top;
+        for _ in 0..self.layers.len() {
+            cur = cur.inner().ok_or_else(|| {
+                ZKError::new(ErrorEnumsStruct::MIDDLEWARE_CHAIN_INVALID.get_code(), "chain shorter than its layers".to_string())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// writes buffered below the layers stay there, only the ones drained from a cache layer
+    /// need a cache in the new layers to land in
+    fn check_buffering(&self, pending: &[Operation], layers: &[MiddlewareType]) -> ZKResult<()> {
+        let cached = |layers: &[MiddlewareType]| layers.contains(&MiddlewareType::Cache);
+        if !pending.is_empty() && cached(self.layers.as_slice()) && !cached(layers) {
+            return Err(ZKError::new(ErrorEnumsStruct::MIDDLEWARE_CHAIN_INVALID.get_code(),
+                                    format!("{} buffered writes need a cache layer, commit them first", pending.len())));
+        }
+        Ok(())
+    }
+
+    fn top(&self) -> &dyn TreeMiddleware {
+        self.top.as_deref().expect("chain is being reconfigured")
+    }
+
+    fn top_mut(&mut self) -> &mut dyn TreeMiddleware {
+        self.top.as_deref_mut().expect("chain is being reconfigured")
+    }
+}
+
+impl DB for MiddlewareChain {
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        self.top().get(k)
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        self.top_mut().set(k, v)
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        self.top_mut().delete(k)
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        self.top().get_many(keys)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.top().range(start, end)
+    }
+
+    fn write_batch(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        self.top_mut().write_batch(operations)
+    }
+
+    fn compare_and_set(&mut self, k: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> ZKResult<()> {
+        self.top_mut().compare_and_set(k, expected, new)
+    }
+}
+
+impl TreeDB for MiddlewareChain {
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        self.top().prove(req)
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        self.top().verify(req)
+    }
+
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        self.top_mut().commit(operations)
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.top().root_hash()
+    }
+
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        self.top_mut().prepare(operations)
+    }
+
+    fn commit_if(&mut self, conditions: Vec<Precondition>, operations: Vec<Operation>) -> ZKResult<()> {
+        self.top_mut().commit_if(conditions, operations)
+    }
+}
+
+impl TreeMiddleware for MiddlewareChain {
+    fn name(&self) -> &'static str {
+        "chain"
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        Some(self.top())
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        Some(self.top_mut())
+    }
+
+    fn into_inner(mut self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        self.top.take()
+    }
+
+    /// cleans every layer of the chain
+    fn clean(&mut self) -> ZKResult<()> {
+        let mut cur = Some(self.top_mut());
+        while let Some(m) = cur {
+            m.clean()?;
+            cur = m.inner_mut();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::ErrorEnumsStruct;
+    use crate::middleware::chain::MiddlewareChain;
+    use crate::middleware::middleware::{DBMiddleware, MiddlewareType, TreeMiddleware};
+    use crate::middleware::validate::ValidationConfig;
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::tree::{DB, TreeDB};
+
+    fn new_chain(layers: Vec<MiddlewareType>) -> MiddlewareChain {
+        MiddlewareChain::new(Box::new(DBMiddleware::new(MemoryTreeDB::new())), layers).expect("fail to build")
+    }
+
+    #[test]
+    pub fn test_bottom_is_defined() {
+        let chain = new_chain(vec![MiddlewareType::Cache]);
+        let top = chain.inner().unwrap();
+        assert_eq!(top.name(), "cache");
+        let bottom = top.inner().unwrap();
+        assert_eq!(bottom.name(), "db");
+        assert!(bottom.inner().is_none());
+    }
+
+    #[test]
+    pub fn test_reconfigure_keeps_pending_writes() {
+        let mut chain = new_chain(vec![MiddlewareType::Cache]);
+        let empty = chain.root_hash();
+        chain.set(&[1], vec![1]).expect("fail to set");
+        assert_eq!(chain.root_hash(), empty);
+
+        let err = chain.remove(0).expect_err("buffered writes need a cache");
+        assert_eq!(err.get_code(), ErrorEnumsStruct::MIDDLEWARE_CHAIN_INVALID.get_code());
+        assert_eq!(chain.layers(), &[MiddlewareType::Cache]);
+        assert_eq!(chain.root_hash(), empty);
+
+        let strict = MiddlewareType::Validation(ValidationConfig::new(8, 8));
+        chain.reconfigure(vec![strict.clone(), MiddlewareType::Cache]).expect("fail to reconfigure");
+        assert_eq!(chain.get(&[1]).unwrap(), Some(vec![1].into()));
+        assert_eq!(chain.root_hash(), empty);
+
+        chain.commit(vec![]).expect("fail to commit");
+        assert_ne!(chain.root_hash(), empty);
+        chain.reconfigure(vec![strict]).expect("fail to reconfigure");
+        chain.set(&[2], vec![2]).expect("fail to set");
+        assert_eq!(chain.get(&[2]).unwrap(), Some(vec![2].into()));
+        assert!(chain.remove(3).is_err());
+    }
+
+    #[test]
+    pub fn test_failed_reconfigure_keeps_chain() {
+        let mut chain = new_chain(vec![MiddlewareType::Cache]);
+        let empty = chain.root_hash();
+        chain.set(&[1], vec![0; 8]).expect("fail to set");
+
+        let missing = std::env::temp_dir().join("zkp_chain_missing_dir").join("audit.log");
+        assert!(chain.push(MiddlewareType::Audit(missing)).is_err());
+        assert_eq!(chain.layers(), &[MiddlewareType::Cache]);
+        assert_eq!(chain.get(&[1]).unwrap(), Some(vec![0; 8].into()));
+
+        let strict = MiddlewareType::Validation(ValidationConfig::new(8, 4));
+        assert!(chain.reconfigure(vec![MiddlewareType::Cache, strict]).is_err());
+        assert_eq!(chain.layers(), &[MiddlewareType::Cache]);
+        assert_eq!(chain.get(&[1]).unwrap(), Some(vec![0; 8].into()));
+        assert_eq!(chain.root_hash(), empty);
+        chain.commit(vec![]).expect("fail to commit");
+        assert_ne!(chain.root_hash(), empty);
+    }
+}
@@ -0,0 +1,282 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use crate::error::ZKResult;
+use crate::middleware::middleware::{CacheStats, TreeMiddleware};
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::operation::Operation;
+use crate::tree::tree::{DB, TreeDB};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadCacheConfig {
+    /// upper bound of the cached keys and values in bytes
+    pub max_bytes: usize,
+    pub max_entries: usize,
+}
+
+impl Default for ReadCacheConfig {
+    fn default() -> Self {
+        Self { max_bytes: 64 * 1024 * 1024, max_entries: 1 << 20 }
+    }
+}
+
+/// least recently used map bounded by entries and bytes, absent keys are cached as `None`
+struct Lru {
+    config: ReadCacheConfig,
+    entries: HashMap<Vec<u8>, (Option<Vec<u8>>, u64)>,
+    order: BTreeMap<u64, Vec<u8>>,
+    bytes: usize,
+    tick: u64,
+    stats: CacheStats,
+}
+
+fn entry_size(k: &[u8], v: &Option<Vec<u8>>) -> usize {
+    k.len() + v.as_ref().map_or(0, |v| v.len())
+}
+
+impl Lru {
+    fn new(config: ReadCacheConfig) -> Self {
+        Self { config, entries: HashMap::new(), order: BTreeMap::new(), bytes: 0, tick: 0, stats: CacheStats::default() }
+    }
+
+    fn get(&mut self, k: &[u8]) -> Option<Option<Vec<u8>>> {
+        self.tick += 1;
+        match self.entries.get_mut(k) {
+            Some((v, tick)) => {
+                self.order.remove(tick);
+                *tick = self.tick;
+                self.order.insert(self.tick, k.to_vec());
+                self.stats.hits += 1;
+                Some(v.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, k: Vec<u8>, v: Option<Vec<u8>>) {
+        self.remove(k.as_slice());
+        let size = entry_size(k.as_slice(), &v);
+        if size > self.config.max_bytes || self.config.max_entries == 0 {
+            return;
+        }
+        while self.bytes + size > self.config.max_bytes || self.entries.len() >= self.config.max_entries {
+            let oldest = match self.order.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            let key = self.order.remove(&oldest).unwrap();
+            self.remove(key.as_slice());
+        }
+        self.tick += 1;
+        self.bytes += size;
+        self.order.insert(self.tick, k.clone());
+        self.entries.insert(k, (v, self.tick));
+    }
+
+    fn remove(&mut self, k: &[u8]) {
+        if let Some((v, tick)) = self.entries.remove(k) {
+            self.order.remove(&tick);
+            self.bytes -= entry_size(k, &v);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.bytes = 0;
+    }
+}
+
+/// read-through cache of the values below it, keys are invalidated whenever they are written or committed
+pub struct ReadCacheMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    inner: M,
+    lru: Mutex<Lru>,
+}
+
+impl<M> ReadCacheMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    pub fn new(inner: M, config: ReadCacheConfig) -> Self {
+        Self { inner, lru: Mutex::new(Lru::new(config)) }
+    }
+
+    /// bytes currently held by the cache
+    pub fn size(&self) -> usize {
+        self.lru.lock().unwrap().bytes
+    }
+
+    fn invalidate(&self, k: &[u8]) {
+        self.lru.lock().unwrap().remove(k);
+    }
+}
+
+impl<M> DB for ReadCacheMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    /// values live behind the lock, so hits are handed out owned
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        if let Some(v) = self.lru.lock().unwrap().get(k) {
+            return Ok(v.map(Cow::Owned));
+        }
+        let v = self.inner.get(k)?.map(Cow::into_owned);
+        self.lru.lock().unwrap().insert(k.to_vec(), v.clone());
+        Ok(v.map(Cow::Owned))
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        self.invalidate(k);
+        self.inner.set(k, v)
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        self.invalidate(k);
+        self.inner.delete(k)
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        let mut ret = Vec::with_capacity(keys.len());
+        let mut missed = Vec::new();
+        {
+            let mut lru = self.lru.lock().unwrap();
+            for (i, k) in keys.iter().enumerate() {
+                match lru.get(k) {
+                    Some(v) => ret.push(v),
+                    None => {
+                        missed.push(i);
+                        ret.push(None);
+                    }
+                }
+            }
+        }
+        if missed.is_empty() {
+            return Ok(ret);
+        }
+        let query: Vec<&[u8]> = missed.iter().map(|i| keys[*i]).collect();
+        let values = self.inner.get_many(query.as_slice())?;
+        let mut lru = self.lru.lock().unwrap();
+        for (i, v) in missed.into_iter().zip(values) {
+            lru.insert(keys[i].to_vec(), v.clone());
+            ret[i] = v;
+        }
+        Ok(ret)
+    }
+
+    /// ranges are not cached, they go straight to the layer below
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.range(start, end)
+    }
+}
+
+impl<M> TreeDB for ReadCacheMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        self.inner.prove(req)
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        self.inner.verify(req)
+    }
+
+    /// buffered writes below are drained first so every key touched by the commit is invalidated
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        let ops = self.inner.prepare(operations)?;
+        for op in ops.iter() {
+            match op {
+                Operation::DeleteRange(..) => self.lru.lock().unwrap().clear(),
+                op => self.invalidate(op.key()),
+            }
+        }
+        self.inner.commit(ops).map_err(|e| {
+            self.lru.lock().unwrap().clear();
+            e
+        })
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.inner.root_hash()
+    }
+
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        self.inner.prepare(operations)
+    }
+}
+
+impl<M> TreeMiddleware for ReadCacheMiddleware<M>
+    where
+        M: TreeMiddleware + 'static,
+{
+    fn name(&self) -> &'static str {
+        "read_cache"
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        Some(&self.inner)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        Some(&mut self.inner)
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        Some(Box::new(self.inner))
+    }
+
+    fn clean(&mut self) -> ZKResult<()> {
+        self.lru.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.lru.lock().unwrap().stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::middleware::lru::{ReadCacheConfig, ReadCacheMiddleware};
+    use crate::middleware::middleware::{DBMiddleware, TreeMiddleware};
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::operation::Operation;
+    use crate::tree::tree::{DB, TreeDB};
+
+    #[test]
+    pub fn test_read_through() {
+        let mut cache = ReadCacheMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()), ReadCacheConfig::default());
+        cache.commit(vec![Operation::Set(vec![1], vec![1])]).unwrap();
+        assert_eq!(cache.get(&[1]).unwrap(), Some(vec![1].into()));
+        assert_eq!(cache.get(&[1]).unwrap(), Some(vec![1].into()));
+        assert_eq!(cache.cache_stats().unwrap().hits, 1);
+
+        cache.commit(vec![Operation::Set(vec![1], vec![2])]).unwrap();
+        assert_eq!(cache.get(&[1]).unwrap(), Some(vec![2].into()));
+        cache.delete(&[1]).unwrap();
+        assert_eq!(cache.get(&[1]).unwrap(), None);
+    }
+
+    #[test]
+    pub fn test_evict_by_bytes() {
+        let config = ReadCacheConfig { max_bytes: 10, max_entries: 100 };
+        let mut cache = ReadCacheMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()), config);
+        let ops = (0..4u8).map(|i| Operation::Set(vec![i], vec![i; 4])).collect();
+        cache.commit(ops).unwrap();
+        for i in 0..4u8 {
+            cache.get(&[i]).unwrap();
+        }
+        assert_eq!(cache.size(), 10);
+        cache.get(&[3]).unwrap();
+        cache.get(&[0]).unwrap();
+        let stats = cache.cache_stats().unwrap();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 5);
+    }
+}
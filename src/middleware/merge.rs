@@ -0,0 +1,139 @@
+use std::borrow::Cow;
+use crate::error::ZKResult;
+use crate::middleware::middleware::TreeMiddleware;
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::operation::{resolve_with, MergeOperators, Operation};
+use crate::tree::tree::{DB, TreeDB};
+
+/// lowers `Operation::Merge` with its own operators before writes reach the layers below,
+/// which reject merges. it has to sit above every layer that resolves operations
+pub struct MergeMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    inner: M,
+    operators: MergeOperators,
+}
+
+impl<M> MergeMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    pub fn new(inner: M, operators: MergeOperators) -> Self {
+        Self { inner, operators }
+    }
+}
+
+impl<M> DB for MergeMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        self.inner.get(k)
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        self.inner.set(k, v)
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        self.inner.delete(k)
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        self.inner.get_many(keys)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.range(start, end)
+    }
+
+    fn write_batch(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        let operations = resolve_with(&self.inner, operations, &self.operators)?;
+        self.inner.write_batch(operations)
+    }
+}
+
+impl<M> TreeDB for MergeMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        self.inner.prove(req)
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        self.inner.verify(req)
+    }
+
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        let operations = resolve_with(&self.inner, operations, &self.operators)?;
+        self.inner.commit(operations)
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.inner.root_hash()
+    }
+
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        let operations = resolve_with(&self.inner, operations, &self.operators)?;
+        self.inner.prepare(operations)
+    }
+}
+
+impl<M> TreeMiddleware for MergeMiddleware<M>
+    where
+        M: TreeMiddleware + 'static,
+{
+    fn name(&self) -> &'static str {
+        "merge"
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        Some(&self.inner)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        Some(&mut self.inner)
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        Some(Box::new(self.inner))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use crate::error::ErrorEnumsStruct;
+    use crate::middleware::cache::CacheMiddleware;
+    use crate::middleware::merge::MergeMiddleware;
+    use crate::middleware::middleware::DBMiddleware;
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::operation::{MergeOperators, Operation};
+    use crate::tree::tree::{DB, TreeDB};
+
+    #[test]
+    pub fn test_stacks_keep_their_operators() {
+        let append = MergeOperators::new().with_operator(b"log/", Arc::new(|_, cur, operand| {
+            Ok([cur.unwrap_or_default(), operand].concat())
+        }));
+        let replace = MergeOperators::new().with_operator(b"log/", Arc::new(|_, _, operand| Ok(operand.to_vec())));
+        let mut a = MergeMiddleware::new(CacheMiddleware::new(DBMiddleware::new(MemoryTreeDB::new())), append);
+        let mut b = MergeMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()), replace);
+
+        for db in [&mut a as &mut dyn TreeDB, &mut b] {
+            db.set(b"log/1", vec![1]).expect("fail to set");
+            db.write_batch(vec![Operation::Merge(b"log/1".to_vec(), vec![2])]).expect("fail to write");
+            db.commit(vec![]).expect("fail to commit");
+            db.commit(vec![Operation::Merge(b"log/1".to_vec(), vec![3])]).expect("fail to commit");
+        }
+        assert_eq!(a.get(b"log/1").unwrap(), Some(vec![1, 2, 3].into()));
+        assert_eq!(b.get(b"log/1").unwrap(), Some(vec![3].into()));
+
+        let err = a.commit(vec![Operation::Merge(b"meta".to_vec(), vec![1])]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::OPERATION_INVALID.get_code());
+        let mut plain = DBMiddleware::new(MemoryTreeDB::new());
+        assert!(plain.commit(vec![Operation::Merge(b"log/1".to_vec(), vec![1])]).is_err());
+    }
+}
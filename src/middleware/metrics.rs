@@ -0,0 +1,342 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use crate::error::ZKResult;
+use crate::middleware::middleware::{CacheStats, TreeMiddleware};
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::operation::Operation;
+use crate::tree::tree::{DB, TreeDB};
+
+/// upper bounds of the latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+/// upper bounds of the bytes written per commit buckets
+const BYTES_BUCKETS: [f64; 8] = [1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TreeOp {
+    Get,
+    Set,
+    Delete,
+    Commit,
+    Prove,
+    Verify,
+}
+
+impl TreeOp {
+    const ALL: [TreeOp; 6] = [TreeOp::Get, TreeOp::Set, TreeOp::Delete, TreeOp::Commit, TreeOp::Prove, TreeOp::Verify];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TreeOp::Get => "get",
+            TreeOp::Set => "set",
+            TreeOp::Delete => "delete",
+            TreeOp::Commit => "commit",
+            TreeOp::Prove => "prove",
+            TreeOp::Verify => "verify",
+        }
+    }
+}
+
+/// cumulative histogram with fixed buckets
+pub struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    scale: f64,
+}
+
+impl Histogram {
+    /// observations are recorded as integers and divided by `scale` when rendered
+    fn new(bounds: &'static [f64], scale: f64) -> Self {
+        Self {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            scale,
+        }
+    }
+
+    fn observe(&self, v: u64) {
+        let value = v as f64 / self.scale;
+        for (i, bound) in self.bounds.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[i].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(v, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (i, bound) in self.bounds.iter().enumerate() {
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, bound, self.buckets[i].load(Ordering::Relaxed));
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, self.count());
+        let braces = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces, self.sum.load(Ordering::Relaxed) as f64 / self.scale);
+        let _ = writeln!(out, "{}_count{} {}", name, braces, self.count());
+    }
+}
+
+/// counters shared between a `MetricsMiddleware` and whoever renders them
+pub struct Metrics {
+    latency: Vec<Histogram>,
+    errors: Vec<AtomicU64>,
+    commit_bytes: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            latency: TreeOp::ALL.iter().map(|_| Histogram::new(&LATENCY_BUCKETS, 1e9)).collect(),
+            errors: TreeOp::ALL.iter().map(|_| AtomicU64::new(0)).collect(),
+            commit_bytes: Histogram::new(&BYTES_BUCKETS, 1.0),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn count(&self, op: TreeOp) -> u64 {
+        self.latency[op as usize].count()
+    }
+
+    pub fn errors(&self, op: TreeOp) -> u64 {
+        self.errors[op as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn commit_bytes(&self) -> &Histogram {
+        &self.commit_bytes
+    }
+
+    fn record<T>(&self, op: TreeOp, start: Instant, ret: &ZKResult<T>) {
+        self.latency[op as usize].observe(start.elapsed().as_nanos() as u64);
+        if ret.is_err() {
+            self.errors[op as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// prometheus text exposition format
+    pub fn render(&self, cache: Option<CacheStats>) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP zk_tree_operation_duration_seconds latency of tree operations\n");
+        out.push_str("# TYPE zk_tree_operation_duration_seconds histogram\n");
+        for op in TreeOp::ALL.iter() {
+            self.latency[*op as usize].render(&mut out, "zk_tree_operation_duration_seconds", &format!("op=\"{}\"", op.as_str()));
+        }
+        out.push_str("# HELP zk_tree_operation_errors_total failed tree operations\n");
+        out.push_str("# TYPE zk_tree_operation_errors_total counter\n");
+        for op in TreeOp::ALL.iter() {
+            let _ = writeln!(out, "zk_tree_operation_errors_total{{op=\"{}\"}} {}", op.as_str(), self.errors(*op));
+        }
+        out.push_str("# HELP zk_tree_commit_bytes bytes written per commit\n");
+        out.push_str("# TYPE zk_tree_commit_bytes histogram\n");
+        self.commit_bytes.render(&mut out, "zk_tree_commit_bytes", "");
+        if let Some(stats) = cache {
+            out.push_str("# HELP zk_tree_cache_requests_total cache lookups by result\n");
+            out.push_str("# TYPE zk_tree_cache_requests_total counter\n");
+            let _ = writeln!(out, "zk_tree_cache_requests_total{{result=\"hit\"}} {}", stats.hits);
+            let _ = writeln!(out, "zk_tree_cache_requests_total{{result=\"miss\"}} {}", stats.misses);
+            out.push_str("# HELP zk_tree_cache_hit_ratio hits over all cache lookups\n");
+            out.push_str("# TYPE zk_tree_cache_hit_ratio gauge\n");
+            let _ = writeln!(out, "zk_tree_cache_hit_ratio {}", stats.hit_ratio());
+        }
+        out
+    }
+}
+
+/// shared metrics, lets a metrics layer be described in a `MiddlewareType` list
+#[derive(Clone, Default)]
+pub struct MetricsHandle(Arc<Metrics>);
+
+impl MetricsHandle {
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.0.clone()
+    }
+}
+
+impl std::fmt::Debug for MetricsHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MetricsHandle")
+    }
+}
+
+impl PartialEq for MetricsHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+pub struct MetricsMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    inner: M,
+    metrics: Arc<Metrics>,
+}
+
+impl<M> MetricsMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    pub fn new(inner: M, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// renders the metrics together with the cache statistics of the layers below
+    pub fn render_prometheus(&self) -> String {
+        self.metrics.render(self.inner.cache_stats())
+    }
+}
+
+impl<M> DB for MetricsMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        let start = Instant::now();
+        let ret = self.inner.get(k);
+        self.metrics.record(TreeOp::Get, start, &ret);
+        ret
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        let start = Instant::now();
+        let ret = self.inner.set(k, v);
+        self.metrics.record(TreeOp::Set, start, &ret);
+        ret
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        let start = Instant::now();
+        let ret = self.inner.delete(k);
+        self.metrics.record(TreeOp::Delete, start, &ret);
+        ret
+    }
+
+    /// recorded as a single get
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        let start = Instant::now();
+        let ret = self.inner.get_many(keys);
+        self.metrics.record(TreeOp::Get, start, &ret);
+        ret
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let begin = Instant::now();
+        let ret = self.inner.range(start, end);
+        self.metrics.record(TreeOp::Get, begin, &ret);
+        ret
+    }
+}
+
+impl<M> TreeDB for MetricsMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        let start = Instant::now();
+        let ret = self.inner.prove(req);
+        self.metrics.record(TreeOp::Prove, start, &ret);
+        ret
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        let start = Instant::now();
+        let ret = self.inner.verify(req);
+        self.metrics.record(TreeOp::Verify, start, &ret);
+        ret
+    }
+
+    /// drains the layers below first so the bytes of buffered writes are counted as well
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        let start = Instant::now();
+        let ret = self.inner.prepare(operations).and_then(|ops| {
+            let bytes: usize = ops.iter().map(|op| match op {
+                Operation::Set(k, v) => k.len() + v.len(),
+                Operation::Delete(k) => k.len(),
+                Operation::Increment(k, _) => k.len() + 16,
+                Operation::Merge(k, operand) => k.len() + operand.len(),
+                Operation::DeleteRange(start, end) => start.len() + end.len(),
+            }).sum();
+            self.inner.commit(ops).map(|_| bytes)
+        });
+        if let Ok(bytes) = ret.as_ref() {
+            self.metrics.commit_bytes.observe(*bytes as u64);
+        }
+        self.metrics.record(TreeOp::Commit, start, &ret);
+        ret.map(|_| ())
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.inner.root_hash()
+    }
+
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        self.inner.prepare(operations)
+    }
+}
+
+impl<M> TreeMiddleware for MetricsMiddleware<M>
+    where
+        M: TreeMiddleware + 'static,
+{
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        Some(&self.inner)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        Some(&mut self.inner)
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        Some(Box::new(self.inner))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use crate::middleware::cache::CacheMiddleware;
+    use crate::middleware::metrics::{Metrics, MetricsMiddleware, TreeOp};
+    use crate::middleware::middleware::DBMiddleware;
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::tree::{DB, TreeDB};
+
+    #[test]
+    pub fn test_metrics() {
+        let cache = CacheMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()));
+        let mut m = MetricsMiddleware::new(cache, Arc::new(Metrics::default()));
+        m.set(&[1, 2], vec![3, 4, 5]).unwrap();
+        m.get(&[1, 2]).unwrap();
+        m.get(&[9]).unwrap();
+        m.commit(vec![]).unwrap();
+
+        let metrics = m.metrics();
+        assert_eq!(metrics.count(TreeOp::Get), 2);
+        assert_eq!(metrics.count(TreeOp::Commit), 1);
+        assert_eq!(metrics.errors(TreeOp::Commit), 0);
+
+        let text = m.render_prometheus();
+        assert!(text.contains("zk_tree_operation_duration_seconds_count{op=\"get\"} 2"));
+        assert!(text.contains("zk_tree_commit_bytes_sum 5"));
+        assert!(text.contains("zk_tree_cache_requests_total{result=\"hit\"} 1"));
+        assert!(text.contains("zk_tree_cache_requests_total{result=\"miss\"} 1"));
+    }
+}
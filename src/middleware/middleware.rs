@@ -1,27 +1,98 @@
+use std::borrow::Cow;
 use std::cell::Cell;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::marker::PhantomData;
 use std::ops::IndexMut;
+use std::path::PathBuf;
 use std::sync::Arc;
 use merk::{Batch, BatchEntry, Op};
 use derive_builder::Builder;
 use crate::error::ZKResult;
+use crate::middleware::lru::ReadCacheConfig;
+use crate::middleware::validate::ValidationConfig;
+use crate::middleware::metrics::MetricsHandle;
 use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
-use crate::tree::operation::Operation;
-use crate::tree::tree::{DB, TreeDB};
+use crate::tree::operation::{MergeOperators, Operation};
+use crate::tree::tree::{Visitor, DB, TreeDB};
 
 
+/// one layer of a middleware chain, object safe so layers can be stacked as `Box<dyn TreeMiddleware>`
 pub trait TreeMiddleware: TreeDB {
-    type Inner: TreeMiddleware;
-    fn inner(&self) -> &Self::Inner;
+    fn name(&self) -> &'static str;
+    /// the layer below, `None` at the bottom of the chain
+    fn inner(&self) -> Option<&dyn TreeMiddleware>;
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware>;
+    /// unwraps this layer, `None` at the bottom of the chain
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>>;
     fn clean(&mut self) -> ZKResult<()> {
         Ok(())
     }
+    /// read statistics of the closest cache in the chain
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner().and_then(|i| i.cache_stats())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
 }
 
+impl CacheStats {
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+impl<T: TreeMiddleware + ?Sized> TreeMiddleware for Box<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        (**self).inner()
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        (**self).inner_mut()
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        T::into_inner(*self)
+    }
+
+    fn clean(&mut self) -> ZKResult<()> {
+        (**self).clean()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        (**self).cache_stats()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DBType {
-    Merkle
+    Merkle,
+    SMT,
+    InMemory,
+}
+
+/// middlewares which can be stacked on top of a backend from configuration, listed from the bottom up
+#[derive(Debug, Clone, PartialEq)]
+pub enum MiddlewareType {
+    Cache,
+    Metrics(MetricsHandle),
+    /// path of the audit log
+    Audit(PathBuf),
+    ReadCache(ReadCacheConfig),
+    Validation(ValidationConfig),
+    Merge(MergeOperators),
 }
 
 
@@ -80,86 +151,114 @@ impl<D> TreeDB for DBMiddleware<D>
     fn root_hash(&self) -> [u8; 32] {
         self.db.root_hash()
     }
+
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        self.db.prepare(operations)
+    }
 }
 
 impl<D> DB for DBMiddleware<D>
     where
         D: TreeDB,
 {
-    fn get(&self, k: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
-        self.db.get(k.clone())
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        self.db.get(k)
     }
 
-    fn set(&mut self, k: Vec<u8>, v: Vec<u8>) -> ZKResult<Vec<u8>> {
-        self.db.set(k.clone(), v)?;
-        Ok(k.clone())
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        self.db.set(k, v)
     }
 
 
-    fn delete(&mut self, k: Vec<u8>) -> ZKResult<()> {
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
         self.db.delete(k)
     }
+
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        self.db.get_many(keys)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.db.range(start, end)
+    }
+
+    fn scan(&self, start: &[u8], end: &[u8], f: &mut Visitor) -> ZKResult<()> {
+        self.db.scan(start, end, f)
+    }
 }
 
+/// the bottom of every chain, adapts a plain `TreeDB` backend
 impl<D> TreeMiddleware for DBMiddleware<D>
     where
         D: TreeDB,
 {
-    type Inner = Self;
+    fn name(&self) -> &'static str {
+        "db"
+    }
 
-    fn inner(&self) -> &Self::Inner {
-        unimplemented!()
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        None
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        None
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        None
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::borrow::Cow;
     use std::sync::Arc;
-    use merk::{Hash, Merk};
     use crate::middleware::cache::CacheMiddleware;
     use crate::tree::tree::TreeDB;
-    use crate::middleware::middleware::{DBMiddleware, TreeMiddleware};
+    use crate::middleware::builder::{HasherType, TreeBuilder};
+    use crate::middleware::middleware::{DBMiddleware, DBType, MiddlewareType, TreeMiddleware};
     use crate::tree::tree::DB;
     use crate::tree::couple::{ProveRequest, VerifyRequest};
-    use crate::tree::merkle::MerkleRocksDB;
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::error::ErrorEnumsStruct;
+    use crate::tree::operation::{encode_counter, Operation, Precondition};
 
 
     #[test]
     pub fn test_cache_get() {
-        let mut cache = new_cache_merkle();
-        let res = cache.get(vec![1, 2, 3]).expect("fail to get");
+        let mut cache = new_cache_memory();
+        let res = cache.get(&[1, 2, 3]).expect("fail to get");
         assert_eq!(res, None)
     }
 
     #[test]
     pub fn test_set() {
-        let mut cache = new_cache_merkle();
-        cache.set(vec![1, 2, 3], vec![4, 5, 6]).expect("fail to set");
-        let ret = cache.get(vec![1, 2, 3]).expect("fail to get").unwrap();
+        let mut cache = new_cache_memory();
+        cache.set(&[1, 2, 3], vec![4, 5, 6]).expect("fail to set");
+        let ret = cache.get(&[1, 2, 3]).expect("fail to get").unwrap();
         assert_eq!(ret, vec![4, 5, 6])
     }
 
     #[test]
     pub fn test_commit() {
-        let mut cache = new_cache_merkle();
-        cache.set(vec![4, 5, 6], vec![1, 1, 1]).expect("fail to set ");
-        cache.set(vec![1, 2, 3], vec![4, 5, 6]).expect("fail to set");
+        let mut cache = new_cache_memory();
+        cache.set(&[4, 5, 6], vec![1, 1, 1]).expect("fail to set ");
+        cache.set(&[1, 2, 3], vec![4, 5, 6]).expect("fail to set");
 
         cache.commit(vec![]).expect("fail to commit");
     }
 
-    fn new_cache_merkle() -> impl TreeMiddleware {
-        let mut merk = Merk::open("./merk.db").unwrap();
-        let internal = MerkleRocksDB::new(merk);
-        let db_middleware: DBMiddleware<MerkleRocksDB> = DBMiddleware::new(internal);
+    fn new_cache_memory() -> impl TreeMiddleware {
+        let internal = MemoryTreeDB::new();
+        let db_middleware: DBMiddleware<MemoryTreeDB> = DBMiddleware::new(internal);
         let mut cache = CacheMiddleware::new(db_middleware);
         return cache;
     }
 
     #[test]
     pub fn test_prove_verify() {
-        let mut mid = new_cache_merkle();
-        mid.set(vec![1, 2, 3], vec![4, 5, 6]).expect("fail to set");
+        let mut mid = new_cache_memory();
+        mid.set(&[1, 2, 3], vec![4, 5, 6]).expect("fail to set");
         mid.commit(vec![]).expect("fail to commit");
         let mut req = ProveRequest::default();
         req.insert(vec![1, 2, 3]);
@@ -173,6 +272,102 @@ mod test {
     }
 
     #[test]
-    pub fn test_builder() {}
-}
+    pub fn test_builder() {
+        let mut tree = TreeBuilder::new(DBType::InMemory)
+            .middleware(MiddlewareType::Cache)
+            .build()
+            .expect("fail to build");
+        tree.set(&[1, 2, 3], vec![4, 5, 6]).expect("fail to set");
+        assert_eq!(tree.root_hash(), MemoryTreeDB::new().root_hash());
+        tree.commit(vec![]).expect("fail to commit");
+
+        let mut expected = MemoryTreeDB::new();
+        expected.set(&[1, 2, 3], vec![4, 5, 6]).unwrap();
+        assert_eq!(tree.root_hash(), expected.root_hash());
+
+        let smt = TreeBuilder::new(DBType::SMT).hasher(HasherType::Poseidon).build();
+        assert!(smt.is_ok());
+        let merkle = TreeBuilder::new(DBType::Merkle).build();
+        assert!(merkle.is_err());
+    }
 
+    #[test]
+    pub fn test_get_many() {
+        let mut cache = new_cache_memory();
+        cache.commit(vec![Operation::Set(vec![1], vec![1]), Operation::Set(vec![2], vec![2])]).expect("fail to commit");
+        cache.set(&[2], vec![20]).expect("fail to set");
+        cache.delete(&[1]).expect("fail to delete");
+        cache.set(&[3], vec![3]).expect("fail to set");
+
+        let res = cache.get_many(&[&[1], &[2], &[3], &[4]]).expect("fail to get");
+        assert_eq!(res, vec![None, Some(vec![20]), Some(vec![3]), None]);
+        let stats = cache.cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses), (3, 1));
+        assert!(matches!(cache.get(&[2]).unwrap(), Some(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    pub fn test_set_returns_previous() {
+        let mut cache = new_cache_memory();
+        cache.commit(vec![Operation::Set(vec![1], vec![1])]).expect("fail to commit");
+        assert_eq!(cache.set(&[1], vec![2]).expect("fail to set"), Some(vec![1]));
+        assert_eq!(cache.set(&[1], vec![3]).expect("fail to set"), Some(vec![2]));
+        assert_eq!(cache.set(&[2], vec![2]).expect("fail to set"), None);
+        assert_eq!(cache.delete(&[1]).expect("fail to delete"), Some(vec![3]));
+        assert_eq!(cache.delete(&[1]).expect("fail to delete"), None);
+    }
+
+    #[test]
+    pub fn test_compare_and_set() {
+        let mut cache = new_cache_memory();
+        cache.commit(vec![Operation::Set(vec![1], vec![1])]).expect("fail to commit");
+        cache.compare_and_set(&[1], Some(&[1]), Some(vec![2])).expect("fail to swap");
+        assert!(cache.compare_and_set(&[1], Some(&[1]), Some(vec![3])).is_err());
+        cache.compare_and_set(&[2], None, Some(vec![2])).expect("fail to insert");
+        cache.compare_and_set(&[2], Some(&[2]), None).expect("fail to remove");
+        assert_eq!(cache.get(&[1]).unwrap(), Some(vec![2].into()));
+
+        let root = cache.root_hash();
+        let cond = vec![Precondition::new(vec![1], Some(vec![2])), Precondition::new(vec![3], Some(vec![3]))];
+        let err = cache.commit_if(cond, vec![Operation::Set(vec![4], vec![4])]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::PRECONDITION_FAILED.get_code());
+        assert_eq!(cache.root_hash(), root);
+        assert_eq!(cache.get(&[4]).unwrap(), None);
+
+        let cond = vec![Precondition::new(vec![1], Some(vec![2])), Precondition::new(vec![3], None)];
+        cache.commit_if(cond, vec![Operation::Set(vec![4], vec![4])]).expect("fail to commit");
+        assert_ne!(cache.root_hash(), root);
+    }
+
+    #[test]
+    pub fn test_extended_operations() {
+        let mut cache = new_cache_memory();
+        cache.commit(vec![
+            Operation::Set(vec![1], encode_counter(1)),
+            Operation::Set(vec![2, 1], vec![1]),
+            Operation::Set(vec![2, 2], vec![2]),
+        ]).expect("fail to commit");
+
+        cache.set(&[1], encode_counter(5)).expect("fail to set");
+        cache.set(&[2, 3], vec![3]).expect("fail to set");
+        cache.write_batch(vec![Operation::Increment(vec![1], 2), Operation::DeleteRange(vec![2, 2], vec![3])]).expect("fail to write");
+        assert_eq!(cache.get(&[1]).unwrap(), Some(encode_counter(7).into()));
+        assert_eq!(cache.range(&[2], &[3]).unwrap(), vec![(vec![2, 1], vec![1])]);
+
+        // operations passed to commit go before the buffered writes, a buffered write wins
+        cache.commit(vec![Operation::Increment(vec![1], 3), Operation::Set(vec![2, 3], vec![9])]).expect("fail to commit");
+        assert_eq!(cache.get(&[1]).unwrap(), Some(encode_counter(7).into()));
+        assert_eq!(cache.get(&[2, 3]).unwrap(), None);
+        cache.commit(vec![Operation::Increment(vec![1], 3)]).expect("fail to commit");
+        assert_eq!(cache.get(&[1]).unwrap(), Some(encode_counter(10).into()));
+        assert_eq!(cache.get(&[2, 2]).unwrap(), None);
+        assert_eq!(cache.range(&[0], &[3]).unwrap().len(), 2);
+
+        // an empty end leaves the range open, for reads and deletes alike
+        cache.set(&[3], vec![3]).expect("fail to set");
+        assert_eq!(cache.range(&[2, 2], &[]).unwrap(), vec![(vec![3], vec![3])]);
+        cache.write_batch(vec![Operation::DeleteRange(vec![2, 2], vec![])]).expect("fail to write");
+        cache.commit(vec![]).expect("fail to commit");
+        assert_eq!(cache.range(&[], &[]).unwrap(), vec![(vec![1], encode_counter(10)), (vec![2, 1], vec![1])]);
+    }
+}
@@ -1,2 +1,11 @@
 pub mod middleware;
-pub mod cache;
\ No newline at end of file
+pub mod cache;
+pub mod builder;
+pub mod chain;
+pub mod metrics;
+pub mod audit;
+pub mod lru;
+pub mod namespace;
+pub mod validate;
+pub mod readonly;
+pub mod merge;
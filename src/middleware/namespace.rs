@@ -0,0 +1,372 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::middleware::middleware::{CacheStats, TreeMiddleware};
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::operation::{resolve, Operation, Precondition};
+use crate::tree::tree::{precondition_failed, prefix_end, DB, TreeDB};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Namespace {
+    pub name: String,
+    pub prefix: Vec<u8>,
+}
+
+impl Namespace {
+    pub fn new(name: &str, prefix: &[u8]) -> Self {
+        Self { name: name.to_string(), prefix: prefix.to_vec() }
+    }
+}
+
+/// each grant includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Access {
+    Read,
+    /// writes are buffered in the shared tree until a handle with `Commit` flushes them
+    ReadWrite,
+    /// flushes the writes buffered in the namespace, whichever handle wrote them
+    Commit,
+}
+
+/// one tree shared by several components, each of them gets its own handle with its own grants
+pub struct NamespaceRegistry<M>
+    where
+        M: TreeMiddleware,
+{
+    tree: Arc<Mutex<M>>,
+    namespaces: Arc<Vec<Namespace>>,
+}
+
+impl<M> NamespaceRegistry<M>
+    where
+        M: TreeMiddleware,
+{
+    pub fn new(tree: M, namespaces: Vec<Namespace>) -> Self {
+        Self { tree: Arc::new(Mutex::new(tree)), namespaces: Arc::new(namespaces) }
+    }
+
+    /// namespaces missing from `grants` can neither be read nor written through the handle
+    pub fn handle(&self, grants: &[(&str, Access)]) -> ZKResult<AccessControlMiddleware<M>> {
+        let mut ret = HashMap::new();
+        for (name, access) in grants {
+            if !self.namespaces.iter().any(|n| n.name == *name) {
+                return Err(ZKError::new(ErrorEnumsStruct::NAMESPACE_UNKNOWN.get_code(), format!("unknown namespace {}", name)));
+            }
+            ret.insert(name.to_string(), *access);
+        }
+        Ok(AccessControlMiddleware { tree: self.tree.clone(), namespaces: self.namespaces.clone(), grants: ret })
+    }
+}
+
+/// handle over a shared tree, every key has to fall into a namespace the handle was granted.
+/// the shared tree sits behind a lock, so the handle is the bottom of its own chain
+pub struct AccessControlMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    tree: Arc<Mutex<M>>,
+    namespaces: Arc<Vec<Namespace>>,
+    grants: HashMap<String, Access>,
+}
+
+impl<M> AccessControlMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    /// the longest prefix wins when namespaces are nested
+    fn namespace_of(&self, k: &[u8]) -> ZKResult<&Namespace> {
+        self.namespaces.iter()
+            .filter(|n| k.starts_with(n.prefix.as_slice()))
+            .max_by_key(|n| n.prefix.len())
+            .ok_or_else(|| ZKError::new(ErrorEnumsStruct::NAMESPACE_UNKNOWN.get_code(),
+                                        format!("key {} is outside of every namespace", hex::encode(k))))
+    }
+
+    fn check_access(&self, k: &[u8], need: Access) -> ZKResult<()> {
+        let ns = self.namespace_of(k)?;
+        match self.grants.get(&ns.name) {
+            Some(access) if *access >= need => Ok(()),
+            _ => {
+                let (code, what) = match need {
+                    Access::Read => (ErrorEnumsStruct::NAMESPACE_READ_FORBIDDEN, "read"),
+                    Access::ReadWrite => (ErrorEnumsStruct::NAMESPACE_WRITE_FORBIDDEN, "write"),
+                    Access::Commit => (ErrorEnumsStruct::NAMESPACE_COMMIT_FORBIDDEN, "commit"),
+                };
+                Err(ZKError::new(code.get_code(), format!("no {} access to namespace {}", what, ns.name)))
+            }
+        }
+    }
+
+    fn check_read(&self, k: &[u8]) -> ZKResult<()> {
+        self.check_access(k, Access::Read)
+    }
+
+    fn check_write(&self, k: &[u8]) -> ZKResult<()> {
+        self.check_access(k, Access::ReadWrite)
+    }
+
+    fn may_commit(&self) -> bool {
+        self.grants.values().any(|a| *a == Access::Commit)
+    }
+
+    fn check_commit(&self) -> ZKResult<()> {
+        if !self.may_commit() {
+            return Err(ZKError::new(ErrorEnumsStruct::NAMESPACE_COMMIT_FORBIDDEN.get_code(), "handle holds no commit grant".to_string()));
+        }
+        Ok(())
+    }
+
+    /// a range delete has to stay inside the namespace of its start, and every namespace nested
+    /// in the range needs `need` as well
+    fn check_op(&self, op: &Operation, need: Access) -> ZKResult<()> {
+        self.check_access(op.key(), need)?;
+        if let Operation::DeleteRange(start, end) = op {
+            let ns = self.namespace_of(start.as_slice())?;
+            if !end.starts_with(ns.prefix.as_slice()) && Some(end.as_slice()) != prefix_end(ns.prefix.as_slice()).as_deref() {
+                return Err(ZKError::new(ErrorEnumsStruct::NAMESPACE_WRITE_FORBIDDEN.get_code(),
+                                        format!("range {}..{} leaves namespace {}", hex::encode(start), hex::encode(end), ns.name)));
+            }
+            for n in self.namespaces.iter().filter(|n| n.prefix > *start && n.prefix < *end) {
+                self.check_access(n.prefix.as_slice(), need)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// drains the writes buffered in `tree` and splits them into the ones this handle may commit
+    /// and the ones it has to leave to other handles
+    fn drain_own(&self, tree: &mut M) -> ZKResult<(Vec<Operation>, Vec<Operation>)> {
+        let drained = tree.prepare(vec![])?;
+        Ok(drained.into_iter().partition(|op| self.check_op(op, Access::Commit).is_ok()))
+    }
+
+    /// commits `operations` with the writes buffered in the namespaces the handle may commit,
+    /// buffered writes win over `operations` like in `CacheMiddleware`. other writes stay buffered
+    fn commit_own(&self, tree: &mut M, operations: Vec<Operation>) -> ZKResult<()> {
+        let (own, others) = self.drain_own(tree)?;
+        if let Err(e) = tree.commit([operations, own.clone()].concat()) {
+            tree.write_batch([own, others].concat())?;
+            return Err(e);
+        }
+        tree.write_batch(others)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, M> {
+        self.tree.lock().unwrap()
+    }
+}
+
+impl<M> DB for AccessControlMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    /// the value can not outlive the lock, so it is always handed out owned
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        self.check_read(k)?;
+        Ok(self.lock().get(k)?.map(|v| Cow::Owned(v.into_owned())))
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        self.check_write(k)?;
+        self.lock().set(k, v)
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        self.check_write(k)?;
+        self.lock().delete(k)
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        for k in keys.iter() {
+            self.check_read(k)?;
+        }
+        self.lock().get_many(keys)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.check_read(start)?;
+        let ret = self.lock().range(start, end)?;
+        for (k, _) in ret.iter() {
+            self.check_read(k.as_slice())?;
+        }
+        Ok(ret)
+    }
+
+    fn write_batch(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        for op in operations.iter() {
+            self.check_op(op, Access::ReadWrite)?;
+        }
+        self.lock().write_batch(operations)
+    }
+
+    /// checked and written under one lock, other handles can not slip a write in between
+    fn compare_and_set(&mut self, k: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> ZKResult<()> {
+        self.check_write(k)?;
+        self.lock().compare_and_set(k, expected, new)
+    }
+}
+
+impl<M> TreeDB for AccessControlMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        for k in req.query.iter() {
+            self.check_read(k.as_slice())?;
+        }
+        self.lock().prove(req)
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        self.lock().verify(req)
+    }
+
+    /// needs a commit grant for every operation. only the writes buffered in namespaces the
+    /// handle may commit are flushed, the whole batch is rejected if any operation leaves them
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        self.check_commit()?;
+        for op in operations.iter() {
+            self.check_op(op, Access::Commit)?;
+        }
+        self.commit_own(&mut self.lock(), operations)
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.lock().root_hash()
+    }
+
+    /// drains only the namespaces the handle may commit. without a commit grant nothing is
+    /// drained, so a chain can still be rebuilt around the handle
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        if operations.is_empty() && !self.may_commit() {
+            return Ok(vec![]);
+        }
+        self.check_commit()?;
+        for op in operations.iter() {
+            self.check_op(op, Access::Commit)?;
+        }
+        let mut tree = self.lock();
+        let (own, others) = self.drain_own(&mut tree)?;
+        let ret = resolve(&*tree, [operations, own.clone()].concat());
+        match ret {
+            Ok(ops) => {
+                tree.write_batch(others)?;
+                Ok(ops)
+            }
+            Err(e) => {
+                tree.write_batch([own, others].concat())?;
+                Err(e)
+            }
+        }
+    }
+
+    fn commit_if(&mut self, conditions: Vec<Precondition>, operations: Vec<Operation>) -> ZKResult<()> {
+        self.check_commit()?;
+        for c in conditions.iter() {
+            self.check_read(c.key.as_slice())?;
+        }
+        for op in operations.iter() {
+            self.check_op(op, Access::Commit)?;
+        }
+        let mut tree = self.lock();
+        for c in conditions.iter() {
+            if tree.get(c.key.as_slice())?.as_deref() != c.expected.as_deref() {
+                return Err(precondition_failed(c.key.as_slice()));
+            }
+        }
+        self.commit_own(&mut tree, operations)
+    }
+}
+
+impl<M> TreeMiddleware for AccessControlMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn name(&self) -> &'static str {
+        "namespace"
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        None
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        None
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        None
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.lock().cache_stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::ErrorEnumsStruct;
+    use crate::middleware::cache::CacheMiddleware;
+    use crate::middleware::middleware::DBMiddleware;
+    use crate::middleware::namespace::{Access, Namespace, NamespaceRegistry};
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::operation::{Operation, Precondition};
+    use crate::tree::tree::{DB, TreeDB};
+
+    #[test]
+    pub fn test_namespace_permissions() {
+        let tree = CacheMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()));
+        let registry = NamespaceRegistry::new(tree, vec![
+            Namespace::new("acct", b"acct/"),
+            Namespace::new("order", b"order/"),
+            Namespace::new("meta", b"meta/"),
+        ]);
+        let mut acct = registry.handle(&[("acct", Access::Commit), ("meta", Access::Read)]).unwrap();
+        let mut order = registry.handle(&[("order", Access::Commit), ("meta", Access::ReadWrite)]).unwrap();
+        let mut reader = registry.handle(&[("acct", Access::Read), ("order", Access::Read), ("meta", Access::Read)]).unwrap();
+
+        acct.set(b"acct/1", vec![1]).expect("fail to set");
+        order.set(b"meta/height", vec![9]).expect("fail to set");
+        assert_eq!(acct.get(b"meta/height").unwrap(), Some(vec![9].into()));
+
+        let err = acct.set(b"order/1", vec![1]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::NAMESPACE_WRITE_FORBIDDEN.get_code());
+        let err = acct.get(b"order/1").unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::NAMESPACE_READ_FORBIDDEN.get_code());
+        let err = order.set(b"other", vec![1]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::NAMESPACE_UNKNOWN.get_code());
+
+        let empty = reader.root_hash();
+        let err = reader.commit(vec![]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::NAMESPACE_COMMIT_FORBIDDEN.get_code());
+        assert!(reader.prepare(vec![]).unwrap().is_empty());
+        assert_eq!(reader.root_hash(), empty);
+        assert_eq!(reader.get(b"acct/1").unwrap(), Some(vec![1].into()));
+
+        assert!(acct.commit(vec![Operation::Set(b"meta/x".to_vec(), vec![1])]).is_err());
+        acct.commit(vec![]).expect("fail to commit");
+        assert_eq!(order.root_hash(), acct.root_hash());
+        let mut only_acct = MemoryTreeDB::new();
+        only_acct.commit(vec![Operation::Set(b"acct/1".to_vec(), vec![1])]).expect("fail to commit");
+        assert_eq!(acct.root_hash(), only_acct.root_hash());
+        assert_eq!(reader.get(b"meta/height").unwrap(), Some(vec![9].into()));
+        assert!(registry.handle(&[("nope", Access::Read)]).is_err());
+
+        order.set(b"order/1", vec![1]).expect("fail to set");
+        let err = order.compare_and_set(b"order/1", Some(&[2]), None).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::PRECONDITION_FAILED.get_code());
+        order.compare_and_set(b"order/1", Some(&[1]), Some(vec![2])).expect("fail to swap");
+        assert!(acct.compare_and_set(b"order/1", Some(&[2]), None).is_err());
+        let cond = vec![Precondition::new(b"order/1".to_vec(), Some(vec![2]))];
+        assert!(acct.commit_if(cond, vec![]).is_err());
+
+        order.set(b"order/2", vec![2]).expect("fail to set");
+        let err = order.commit(vec![Operation::DeleteRange(b"order/".to_vec(), b"p".to_vec())]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::NAMESPACE_WRITE_FORBIDDEN.get_code());
+        order.write_batch(vec![Operation::DeleteRange(b"order/".to_vec(), b"order0".to_vec())]).expect("fail to write");
+        order.commit(vec![]).expect("fail to commit");
+        assert!(order.get(b"order/1").unwrap().is_none());
+        assert!(order.get(b"order/2").unwrap().is_none());
+    }
+}
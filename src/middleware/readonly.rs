@@ -0,0 +1,137 @@
+use std::borrow::Cow;
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::middleware::middleware::TreeMiddleware;
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::operation::Operation;
+use crate::tree::tree::{DB, TreeDB};
+
+/// view of a tree which rejects every write. it never hands out the layer below mutably,
+/// wrap a shared handle such as `AccessControlMiddleware` to read state someone else keeps writing.
+/// reads always see the latest state, none of the backends keeps old roots around to pin a view to
+pub struct ReadOnlyMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    inner: M,
+}
+
+impl<M> ReadOnlyMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+
+    fn read_only(&self, op: &str) -> ZKError {
+        ZKError::new(ErrorEnumsStruct::READ_ONLY.get_code(), format!("{} on a read-only view", op))
+    }
+}
+
+impl<M> DB for ReadOnlyMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        self.inner.get(k)
+    }
+
+    fn set(&mut self, _k: &[u8], _v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        Err(self.read_only("set"))
+    }
+
+    fn delete(&mut self, _k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        Err(self.read_only("delete"))
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        self.inner.get_many(keys)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.range(start, end)
+    }
+}
+
+impl<M> TreeDB for ReadOnlyMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        self.inner.prove(req)
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        self.inner.verify(req)
+    }
+
+    fn commit(&mut self, _operations: Vec<Operation>) -> ZKResult<()> {
+        Err(self.read_only("commit"))
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.inner.root_hash()
+    }
+
+    /// writes buffered below are left where they are, so a chain can still be rebuilt around the view
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        if !operations.is_empty() {
+            return Err(self.read_only("prepare"));
+        }
+        Ok(vec![])
+    }
+}
+
+impl<M> TreeMiddleware for ReadOnlyMiddleware<M>
+    where
+        M: TreeMiddleware + 'static,
+{
+    fn name(&self) -> &'static str {
+        "read_only"
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        Some(&self.inner)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        None
+    }
+
+    /// the layer below is writable, so it is not handed out
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::ErrorEnumsStruct;
+    use crate::middleware::middleware::{DBMiddleware, TreeMiddleware};
+    use crate::middleware::namespace::{Access, Namespace, NamespaceRegistry};
+    use crate::middleware::readonly::ReadOnlyMiddleware;
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::operation::Operation;
+    use crate::tree::tree::{DB, TreeDB};
+
+    #[test]
+    pub fn test_read_only() {
+        let registry = NamespaceRegistry::new(DBMiddleware::new(MemoryTreeDB::new()), vec![Namespace::new("all", b"")]);
+        let mut sequencer = registry.handle(&[("all", Access::Commit)]).unwrap();
+        sequencer.commit(vec![Operation::Set(vec![1], vec![1])]).expect("fail to commit");
+
+        let mut rpc = ReadOnlyMiddleware::new(registry.handle(&[("all", Access::Read)]).unwrap());
+        assert_eq!(rpc.get(&[1]).unwrap(), Some(vec![1].into()));
+        let err = rpc.set(&[1], vec![2]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::READ_ONLY.get_code());
+        assert!(rpc.delete(&[1]).is_err());
+        assert!(rpc.commit(vec![]).is_err());
+        assert!(rpc.inner_mut().is_none());
+        let view = ReadOnlyMiddleware::new(registry.handle(&[("all", Access::Read)]).unwrap());
+        assert!(Box::new(view).into_inner().is_none());
+
+        sequencer.commit(vec![Operation::Set(vec![2], vec![2])]).expect("fail to commit");
+        assert_eq!(rpc.root_hash(), sequencer.root_hash());
+        assert_eq!(rpc.get(&[2]).unwrap(), Some(vec![2].into()));
+    }
+}
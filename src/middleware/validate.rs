@@ -0,0 +1,207 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::middleware::middleware::TreeMiddleware;
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::operation::{resolve, Operation};
+use crate::tree::tree::{DB, TreeDB};
+
+/// decodes or otherwise checks a value, the error message ends up in the returned `ZKError`
+pub type ValueValidator = Arc<dyn Fn(&[u8]) -> Result<(), String> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct ValidationConfig {
+    pub max_key_len: usize,
+    pub max_value_len: usize,
+    validators: Vec<(Vec<u8>, ValueValidator)>,
+}
+
+impl ValidationConfig {
+    pub fn new(max_key_len: usize, max_value_len: usize) -> Self {
+        Self { max_key_len, max_value_len, validators: vec![] }
+    }
+
+    /// every validator whose prefix matches the key runs against the value
+    pub fn with_validator(mut self, prefix: &[u8], validator: ValueValidator) -> Self {
+        self.validators.push((prefix.to_vec(), validator));
+        self
+    }
+
+    pub fn check(&self, k: &[u8], v: &[u8]) -> ZKResult<()> {
+        if k.len() > self.max_key_len {
+            return Err(ZKError::new(ErrorEnumsStruct::KEY_TOO_LONG.get_code(),
+                                    format!("key of {} bytes, limit is {}", k.len(), self.max_key_len)));
+        }
+        if v.len() > self.max_value_len {
+            return Err(ZKError::new(ErrorEnumsStruct::VALUE_TOO_LONG.get_code(),
+                                    format!("value of {} bytes, limit is {}", v.len(), self.max_value_len)));
+        }
+        for (prefix, validator) in self.validators.iter() {
+            if k.starts_with(prefix.as_slice()) {
+                validator(v).map_err(|e| {
+                    ZKError::new(ErrorEnumsStruct::VALUE_INVALID.get_code(), format!("key {}: {}", hex::encode(k), e))
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_ops(&self, operations: &[Operation]) -> ZKResult<()> {
+        for op in operations.iter() {
+            if let Operation::Set(k, v) = op {
+                self.check(k.as_slice(), v.as_slice())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for ValidationConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValidationConfig")
+            .field("max_key_len", &self.max_key_len)
+            .field("max_value_len", &self.max_value_len)
+            .field("validators", &self.validators.iter().map(|(p, _)| hex::encode(p)).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// validators compare by identity, like `MetricsHandle`
+impl PartialEq for ValidationConfig {
+    fn eq(&self, other: &Self) -> bool {
+        self.max_key_len == other.max_key_len
+            && self.max_value_len == other.max_value_len
+            && self.validators.len() == other.validators.len()
+            && self.validators.iter().zip(other.validators.iter()).all(|(a, b)| a.0 == b.0 && Arc::ptr_eq(&a.1, &b.1))
+    }
+}
+
+/// rejects writes that break the configured limits before they reach the layers below.
+/// deletes are never rejected so oversized keys written before the limits can still be removed
+pub struct ValidationMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    inner: M,
+    config: ValidationConfig,
+}
+
+impl<M> ValidationMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    pub fn new(inner: M, config: ValidationConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+impl<M> DB for ValidationMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        self.inner.get(k)
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        self.config.check(k, v.as_slice())?;
+        self.inner.set(k, v)
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        self.inner.delete(k)
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        self.inner.get_many(keys)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.range(start, end)
+    }
+}
+
+impl<M> TreeDB for ValidationMiddleware<M>
+    where
+        M: TreeMiddleware,
+{
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        self.inner.prove(req)
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        self.inner.verify(req)
+    }
+
+    /// increments and merges are resolved first so the value they produce is what gets checked
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        let operations = resolve(&self.inner, operations)?;
+        self.config.check_ops(operations.as_slice())?;
+        self.inner.commit(operations)
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.inner.root_hash()
+    }
+
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        let operations = resolve(&self.inner, operations)?;
+        self.config.check_ops(operations.as_slice())?;
+        self.inner.prepare(operations)
+    }
+}
+
+impl<M> TreeMiddleware for ValidationMiddleware<M>
+    where
+        M: TreeMiddleware + 'static,
+{
+    fn name(&self) -> &'static str {
+        "validation"
+    }
+
+    fn inner(&self) -> Option<&dyn TreeMiddleware> {
+        Some(&self.inner)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn TreeMiddleware> {
+        Some(&mut self.inner)
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<Box<dyn TreeMiddleware>> {
+        Some(Box::new(self.inner))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use crate::error::ErrorEnumsStruct;
+    use crate::middleware::middleware::DBMiddleware;
+    use crate::middleware::validate::{ValidationConfig, ValidationMiddleware};
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::operation::Operation;
+    use crate::tree::tree::{DB, TreeDB};
+
+    #[test]
+    pub fn test_validation() {
+        let config = ValidationConfig::new(8, 32).with_validator(b"acct/", Arc::new(|v: &[u8]| {
+            if v.len() == 16 { Ok(()) } else { Err(format!("account leaf must be 16 bytes, got {}", v.len())) }
+        }));
+        let mut db = ValidationMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()), config);
+        db.set(b"acct/1", vec![0; 16]).expect("fail to set");
+        db.set(b"meta", vec![0; 32]).expect("fail to set");
+
+        let err = db.set(&[0; 9], vec![]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::KEY_TOO_LONG.get_code());
+        let err = db.set(b"meta", vec![0; 33]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::VALUE_TOO_LONG.get_code());
+        let err = db.set(b"acct/2", vec![0; 15]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::VALUE_INVALID.get_code());
+
+        let root = db.root_hash();
+        assert!(db.commit(vec![Operation::Set(b"acct/3".to_vec(), vec![1])]).is_err());
+        assert_eq!(db.root_hash(), root);
+        assert_eq!(db.get(b"acct/2").unwrap(), None);
+        db.delete(&[0; 9]).expect("fail to delete");
+    }
+}
@@ -1,7 +1,47 @@
+use crate::error::ZKResult;
 use crate::middleware::middleware::TreeMiddleware;
+use crate::tree::operation::decode_counter;
+
+const PUBKEY_PREFIX: u8 = 0x01;
+const BALANCE_PREFIX: u8 = 0x02;
+
+/// `0x01 | id`
+pub fn pubkey_key(id: u64) -> Vec<u8> {
+    [&[PUBKEY_PREFIX][..], &id.to_be_bytes()].concat()
+}
+
+/// `0x02 | id | token`, a balance is a counter so transfers can apply `Operation::Increment`
+pub fn balance_key(id: u64, token: u32) -> Vec<u8> {
+    [&[BALANCE_PREFIX][..], &id.to_be_bytes(), &token.to_be_bytes()].concat()
+}
 
 pub struct AccountState<M: TreeMiddleware> {
     tree: M,
 }
 
+impl<M: TreeMiddleware> AccountState<M> {
+    pub fn new(tree: M) -> Self {
+        Self { tree }
+    }
 
+    pub fn pubkey(&self, id: u64) -> ZKResult<Option<Vec<u8>>> {
+        Ok(self.tree.get(pubkey_key(id).as_slice())?.map(|v| v.into_owned()))
+    }
+
+    /// a missing balance is zero
+    pub fn balance(&self, id: u64, token: u32) -> ZKResult<i128> {
+        let k = balance_key(id, token);
+        match self.tree.get(k.as_slice())? {
+            Some(v) => decode_counter(k.as_slice(), &v),
+            None => Ok(0),
+        }
+    }
+
+    pub fn root_hash(&self) -> [u8; 32] {
+        self.tree.root_hash()
+    }
+
+    pub(crate) fn tree_mut(&mut self) -> &mut M {
+        &mut self.tree
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashSet;
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::state::account::{balance_key, pubkey_key};
+use crate::state::order::{market_key, Market};
+use crate::tree::operation::{encode_counter, Operation};
+
+/// the block the genesis state is committed at
+pub const GENESIS_HEIGHT: u64 = 0;
+
+fn invalid(msg: String) -> ZKError {
+    ZKError::new(ErrorEnumsStruct::GENESIS_INVALID.get_code(), msg)
+}
+
+/// initial accounts and markets, read from toml or json:
+///
+/// ```toml
+/// [[accounts]]
+/// id = 1
+/// pubkey = "02ab..."
+/// balances = [{ token = 0, amount = "1000000000000000000" }]
+///
+/// [[markets]]
+/// id = 1
+/// base = 0
+/// quote = 1
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GenesisConfig {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub accounts: Vec<GenesisAccount>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub markets: Vec<GenesisMarket>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GenesisAccount {
+    pub id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))]
+    pub pubkey: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub balances: Vec<GenesisBalance>,
+}
+
+/// `amount` is read from an integer or, past 64 bits, a decimal string
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GenesisBalance {
+    pub token: u32,
+    #[cfg_attr(feature = "serde", serde(with = "amount"))]
+    pub amount: u128,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GenesisMarket {
+    pub id: u32,
+    pub base: u32,
+    pub quote: u32,
+}
+
+impl GenesisConfig {
+    #[cfg(feature = "serde")]
+    pub fn from_json(s: &str) -> ZKResult<Self> {
+        crate::tree::codec::from_json(s)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> ZKResult<Self> {
+        toml::from_str(s).map_err(|e| invalid("malformed toml".to_string()).with_error(Box::new(e)))
+    }
+
+    /// a `.toml` file is read as toml, anything else as json
+    #[cfg(feature = "serde")]
+    pub fn load<P: AsRef<std::path::Path>>(p: P) -> ZKResult<Self> {
+        let s = std::fs::read_to_string(p.as_ref()).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e))
+        })?;
+        match p.as_ref().extension().and_then(|e| e.to_str()) {
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::from_toml(s.as_str()),
+            #[cfg(not(feature = "toml"))]
+            Some("toml") => Err(invalid("toml genesis files need the `toml` feature".to_string())),
+            _ => Self::from_json(s.as_str()),
+        }
+    }
+
+    pub fn validate(&self) -> ZKResult<()> {
+        let mut ids = HashSet::new();
+        for acc in self.accounts.iter() {
+            if !ids.insert(acc.id) {
+                return Err(invalid(format!("duplicate account {}", acc.id)));
+            }
+            if acc.pubkey.is_empty() {
+                return Err(invalid(format!("account {} has no public key", acc.id)));
+            }
+            let mut tokens = HashSet::new();
+            for b in acc.balances.iter() {
+                if !tokens.insert(b.token) {
+                    return Err(invalid(format!("account {} lists token {} twice", acc.id, b.token)));
+                }
+                if i128::try_from(b.amount).is_err() {
+                    return Err(invalid(format!("account {} balance of token {} overflows", acc.id, b.token)));
+                }
+            }
+        }
+        let mut ids = HashSet::new();
+        for m in self.markets.iter() {
+            if !ids.insert(m.id) {
+                return Err(invalid(format!("duplicate market {}", m.id)));
+            }
+            if m.base == m.quote {
+                return Err(invalid(format!("market {} trades token {} against itself", m.id, m.base)));
+            }
+        }
+        Ok(())
+    }
+
+    /// the writes seeding the account tree, zero balances are left out as a missing balance reads as zero
+    pub fn account_operations(&self) -> Vec<Operation> {
+        let mut ops = Vec::new();
+        for acc in self.accounts.iter() {
+            ops.push(Operation::Set(pubkey_key(acc.id), acc.pubkey.clone()));
+            for b in acc.balances.iter().filter(|b| b.amount != 0) {
+                ops.push(Operation::Set(balance_key(acc.id, b.token), encode_counter(b.amount as i128)));
+            }
+        }
+        ops
+    }
+
+    pub fn order_operations(&self) -> Vec<Operation> {
+        self.markets.iter().map(|m| {
+            Operation::Set(market_key(m.id), Market { base: m.base, quote: m.quote }.encode())
+        }).collect()
+    }
+}
+
+#[cfg(feature = "serde")]
+mod amount {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Amount {
+        Number(u64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(v: &u128, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(v.to_string().as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u128, D::Error> {
+        match Amount::deserialize(d)? {
+            Amount::Number(v) => Ok(v as u128),
+            Amount::Text(s) => s.parse().map_err(D::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::ErrorEnumsStruct;
+    use crate::middleware::cache::CacheMiddleware;
+    use crate::middleware::middleware::{DBMiddleware, TreeMiddleware};
+    use crate::middleware::validate::{ValidationConfig, ValidationMiddleware};
+    use crate::state::genesis::{GenesisAccount, GenesisBalance, GenesisConfig, GenesisMarket};
+    use crate::state::order::Market;
+    use crate::state::state::{State, ACCOUNT_TREE, ORDER_TREE};
+    use crate::tree::bundle::global_root;
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::tree::EMPTY_ROOT;
+    use crate::tree::wal::AtomicCommit;
+
+    fn wal_path(name: &str) -> std::path::PathBuf {
+        let p = std::env::temp_dir().join(format!("zkp_genesis_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&p);
+        p
+    }
+
+    fn config() -> GenesisConfig {
+        GenesisConfig {
+            accounts: vec![
+                GenesisAccount {
+                    id: 1,
+                    pubkey: vec![2; 33],
+                    balances: vec![
+                        GenesisBalance { token: 0, amount: 1_000_000_000_000_000_000_000 },
+                        GenesisBalance { token: 1, amount: 500 },
+                    ],
+                },
+                GenesisAccount { id: 2, pubkey: vec![3; 33], balances: vec![] },
+            ],
+            markets: vec![GenesisMarket { id: 7, base: 0, quote: 1 }],
+        }
+    }
+
+    fn new_state() -> State<DBMiddleware<MemoryTreeDB>> {
+        State::new(DBMiddleware::new(MemoryTreeDB::new()), DBMiddleware::new(MemoryTreeDB::new()))
+    }
+
+    #[test]
+    pub fn test_genesis() {
+        let mut state = new_state();
+        let mut committer = AtomicCommit::open(wal_path("apply")).expect("fail to open");
+        let root = state.genesis(&config(), &mut committer).expect("fail to apply genesis");
+
+        assert_eq!(state.accounts().balance(1, 0).unwrap(), 1_000_000_000_000_000_000_000);
+        assert_eq!(state.accounts().balance(1, 1).unwrap(), 500);
+        assert_eq!(state.accounts().balance(2, 0).unwrap(), 0);
+        assert_eq!(state.accounts().pubkey(2).unwrap(), Some(vec![3; 33]));
+        assert_eq!(state.orders().market(7).unwrap(), Some(Market { base: 0, quote: 1 }));
+        assert_eq!(root.root, global_root(&[
+            (ACCOUNT_TREE.to_string(), state.accounts().root_hash()),
+            (ORDER_TREE.to_string(), state.orders().root_hash()),
+        ]));
+
+        let mut again = new_state();
+        let mut committer = AtomicCommit::open(wal_path("again")).expect("fail to open");
+        assert_eq!(again.genesis(&config(), &mut committer).expect("fail to apply genesis"), root);
+
+        let err = state.genesis(&config(), &mut committer).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::GENESIS_INVALID.get_code());
+    }
+
+    #[test]
+    pub fn test_genesis_rejects_invalid_config() {
+        let mut duplicate = config();
+        duplicate.accounts[1].id = 1;
+        let mut self_market = config();
+        self_market.markets[0].quote = 0;
+        let mut overflow = config();
+        overflow.accounts[0].balances[0].amount = u128::MAX;
+        for c in [duplicate, self_market, overflow] {
+            let mut state = new_state();
+            let mut committer = AtomicCommit::open(wal_path("invalid")).expect("fail to open");
+            let err = state.genesis(&c, &mut committer).unwrap_err();
+            assert_eq!(err.get_code(), ErrorEnumsStruct::GENESIS_INVALID.get_code());
+            assert_eq!(state.accounts().pubkey(1).unwrap(), None);
+        }
+    }
+
+    #[test]
+    pub fn test_failed_genesis_leaves_stacks_empty() {
+        let acc: Box<dyn TreeMiddleware> = Box::new(CacheMiddleware::new(DBMiddleware::new(MemoryTreeDB::new())));
+        let strict = ValidationMiddleware::new(CacheMiddleware::new(DBMiddleware::new(MemoryTreeDB::new())), ValidationConfig::new(64, 1));
+        let order: Box<dyn TreeMiddleware> = Box::new(strict);
+        let mut state = State::new(acc, order);
+        let mut committer = AtomicCommit::open(wal_path("clean")).expect("fail to open");
+        assert!(state.genesis(&config(), &mut committer).is_err());
+        assert_eq!(state.accounts().pubkey(1).unwrap(), None);
+        assert_eq!(state.accounts().balance(1, 1).unwrap(), 0);
+    }
+
+    #[test]
+    pub fn test_failed_genesis_on_bare_stacks() {
+        let acc: Box<dyn TreeMiddleware> = Box::new(DBMiddleware::new(MemoryTreeDB::new()));
+        let strict = ValidationMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()), ValidationConfig::new(64, 1));
+        let order: Box<dyn TreeMiddleware> = Box::new(strict);
+        let mut state = State::new(acc, order);
+        let mut committer = AtomicCommit::open(wal_path("bare")).expect("fail to open");
+        let err = state.genesis(&config(), &mut committer).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::VALUE_TOO_LONG.get_code());
+        assert_eq!(state.accounts().root_hash(), EMPTY_ROOT);
+        assert_eq!(state.accounts().pubkey(1).unwrap(), None);
+
+        // the retry is not refused as a non-empty state
+        let err = state.genesis(&config(), &mut committer).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::VALUE_TOO_LONG.get_code());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    pub fn test_genesis_files() {
+        let toml = format!(r#"
+            [[accounts]]
+            id = 1
+            pubkey = "{}"
+            balances = [{{ token = 0, amount = "1000000000000000000000" }}, {{ token = 1, amount = 500 }}]
+
+            [[accounts]]
+            id = 2
+            pubkey = "{}"
+
+            [[markets]]
+            id = 7
+            base = 0
+            quote = 1
+        "#, hex::encode([2; 33]), hex::encode([3; 33]));
+        assert_eq!(GenesisConfig::from_toml(toml.as_str()).expect("fail to parse toml"), config());
+
+        let json = crate::tree::codec::to_json(&config()).expect("fail to serialize");
+        assert_eq!(GenesisConfig::from_json(json.as_str()).expect("fail to parse json"), config());
+
+        let path = std::env::temp_dir().join(format!("zkp_genesis_{}.toml", std::process::id()));
+        std::fs::write(&path, toml).unwrap();
+        assert_eq!(GenesisConfig::load(&path).expect("fail to load"), config());
+        assert!(GenesisConfig::from_toml("[[accounts]]\nid = 1").is_err());
+    }
+}
@@ -1,4 +1,5 @@
-mod account;
-mod order;
+pub mod account;
+pub mod order;
 pub mod event;
 pub mod state;
+pub mod genesis;
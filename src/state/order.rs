@@ -1,7 +1,56 @@
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
 use crate::middleware::middleware::TreeMiddleware;
 
+const MARKET_PREFIX: u8 = 0x01;
+
+/// `0x01 | id`
+pub fn market_key(id: u32) -> Vec<u8> {
+    [&[MARKET_PREFIX][..], &id.to_be_bytes()].concat()
+}
+
+/// a trading pair, stored as `base | quote`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Market {
+    pub base: u32,
+    pub quote: u32,
+}
+
+impl Market {
+    pub fn encode(&self) -> Vec<u8> {
+        [self.base.to_be_bytes(), self.quote.to_be_bytes()].concat()
+    }
+
+    pub fn decode(v: &[u8]) -> ZKResult<Self> {
+        if v.len() != 8 {
+            return Err(ZKError::new(ErrorEnumsStruct::CODEC_INVALID.get_code(), format!("market of {} bytes", v.len())));
+        }
+        Ok(Self {
+            base: u32::from_be_bytes(v[..4].try_into().unwrap()),
+            quote: u32::from_be_bytes(v[4..].try_into().unwrap()),
+        })
+    }
+}
+
 pub  struct  OrderState<M:TreeMiddleware>{
     tree:M,
     
 
-}
\ No newline at end of file
+}
+
+impl<M: TreeMiddleware> OrderState<M> {
+    pub fn new(tree: M) -> Self {
+        Self { tree }
+    }
+
+    pub fn market(&self, id: u32) -> ZKResult<Option<Market>> {
+        self.tree.get(market_key(id).as_slice())?.map(|v| Market::decode(&v)).transpose()
+    }
+
+    pub fn root_hash(&self) -> [u8; 32] {
+        self.tree.root_hash()
+    }
+
+    pub(crate) fn tree_mut(&mut self) -> &mut M {
+        &mut self.tree
+    }
+}
@@ -1,8 +1,15 @@
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
 use crate::middleware::middleware::TreeMiddleware;
 use crate::state::account::AccountState;
 use crate::state::event::{EventInCommand};
+use crate::state::genesis::{GenesisConfig, GENESIS_HEIGHT};
 use crate::state::order::{OrderState};
+use crate::tree::bundle::{global_root, GlobalRoot};
+use crate::tree::tree::EMPTY_ROOT;
+use crate::tree::wal::{AtomicCommit, RecoveryOutcome};
 
+pub const ACCOUNT_TREE: &str = "account";
+pub const ORDER_TREE: &str = "order";
 
 pub struct State<M: TreeMiddleware> {
     acc: AccountState<M>,
@@ -14,5 +21,57 @@ impl<M> State<M>
     where
         M: TreeMiddleware
 {
+    pub fn new(acc: M, order: M) -> Self {
+        Self { acc: AccountState::new(acc), order: OrderState::new(order) }
+    }
+
     pub fn on_event(&mut self, e: EventInCommand) {}
-}
\ No newline at end of file
+
+    pub fn accounts(&self) -> &AccountState<M> {
+        &self.acc
+    }
+
+    pub fn orders(&self) -> &OrderState<M> {
+        &self.order
+    }
+
+    /// the roots of every sub state folded into one, see `global_root`
+    pub fn root(&self) -> GlobalRoot {
+        let trees = vec![
+            (ACCOUNT_TREE.to_string(), self.acc.root_hash()),
+            (ORDER_TREE.to_string(), self.order.root_hash()),
+        ];
+        GlobalRoot { root: global_root(trees.as_slice()), trees }
+    }
+
+    /// seeds empty sub states from `config` and commits them as block `GENESIS_HEIGHT` in one
+    /// atomic step, returns the genesis root. the seed goes through the middleware stacks only as
+    /// part of that block, a failed genesis leaves both sub states as they were
+    pub fn genesis(&mut self, config: &GenesisConfig, committer: &mut AtomicCommit) -> ZKResult<GlobalRoot> {
+        config.validate()?;
+        if self.acc.root_hash() != EMPTY_ROOT || self.order.root_hash() != EMPTY_ROOT {
+            return Err(ZKError::new(ErrorEnumsStruct::GENESIS_INVALID.get_code(), "state is not empty".to_string()));
+        }
+        committer.commit_with(GENESIS_HEIGHT, &mut [
+            (ACCOUNT_TREE, self.acc.tree_mut()),
+            (ORDER_TREE, self.order.tree_mut()),
+        ], vec![config.account_operations(), config.order_operations()])?;
+        Ok(self.root())
+    }
+
+    /// commits every sub state of block `height`, either all of them or none after recovery
+    pub fn commit(&mut self, height: u64, committer: &mut AtomicCommit) -> ZKResult<()> {
+        committer.commit(height, &mut [
+            (ACCOUNT_TREE, self.acc.tree_mut()),
+            (ORDER_TREE, self.order.tree_mut()),
+        ])
+    }
+
+    /// must be called on startup before the first `commit`
+    pub fn recover(&mut self, committer: &mut AtomicCommit) -> ZKResult<RecoveryOutcome> {
+        committer.recover(&mut [
+            (ACCOUNT_TREE, self.acc.tree_mut()),
+            (ORDER_TREE, self.order.tree_mut()),
+        ])
+    }
+}
@@ -0,0 +1,328 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use tiny_keccak::{Hasher, Keccak};
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::middleware::builder::HasherType;
+use crate::tree::codec::{decode_with, header, put_bytes, Codec, Reader, TAG_PROOF_BUNDLE};
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::hasher::{Blake2bHasher, KeccakHasher, PoseidonHasher};
+use crate::tree::merkle::verify_merk_proof;
+use crate::tree::multiproof::{verify_compact, CompactProof};
+use crate::tree::smt::verify_smt_proof;
+use crate::tree::tree::TreeDB;
+
+/// how the proof of an entry was produced, and so how it is checked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProofKind {
+    /// merk and memory trees
+    Merk,
+    /// a merk proof packed as a `CompactProof`
+    CompactMerk,
+    Smt(HasherType),
+}
+
+impl ProofKind {
+    fn tag(&self) -> u8 {
+        match self {
+            ProofKind::Merk => 1,
+            ProofKind::CompactMerk => 2,
+            ProofKind::Smt(HasherType::Keccak) => 3,
+            ProofKind::Smt(HasherType::Blake2b) => 4,
+            ProofKind::Smt(HasherType::Poseidon) => 5,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(ProofKind::Merk),
+            2 => Some(ProofKind::CompactMerk),
+            3 => Some(ProofKind::Smt(HasherType::Keccak)),
+            4 => Some(ProofKind::Smt(HasherType::Blake2b)),
+            5 => Some(ProofKind::Smt(HasherType::Poseidon)),
+            _ => None,
+        }
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        match self {
+            ProofKind::Merk => verify_merk_proof(req),
+            ProofKind::CompactMerk => verify_compact(req),
+            ProofKind::Smt(HasherType::Keccak) => verify_smt_proof::<KeccakHasher>(req),
+            ProofKind::Smt(HasherType::Blake2b) => verify_smt_proof::<Blake2bHasher>(req),
+            ProofKind::Smt(HasherType::Poseidon) => verify_smt_proof::<PoseidonHasher>(req),
+        }
+    }
+}
+
+/// keys of one tree proven against one of its roots. absent keys carry an empty value
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleEntry {
+    pub tree: String,
+    pub kind: ProofKind,
+    pub root: [u8; 32],
+    /// set for proofs taken at an older version, the global root only binds current entries
+    pub version: Option<u64>,
+    pub proof: Vec<u8>,
+    pub kv: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// the roots of several trees folded into one, see `global_root`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalRoot {
+    pub root: [u8; 32],
+    pub trees: Vec<(String, [u8; 32])>,
+}
+
+/// keccak over `count | (len(name) | name | root)*` with the trees sorted by name
+pub fn global_root(trees: &[(String, [u8; 32])]) -> [u8; 32] {
+    let mut sorted: Vec<&(String, [u8; 32])> = trees.iter().collect();
+    sorted.sort();
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(sorted.len() as u32).to_be_bytes());
+    for (name, root) in sorted {
+        put_bytes(&mut buf, name.as_bytes());
+        buf.extend_from_slice(root);
+    }
+    let mut keccak = Keccak::v256();
+    keccak.update(&buf);
+    let mut out = [0u8; 32];
+    keccak.finalize(&mut out);
+    out
+}
+
+/// proofs for keys spread over several trees and versions, shipped as one `ProveResponse`
+/// and checked with a single `verify`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProofBundle {
+    pub entries: Vec<BundleEntry>,
+    pub global: Option<GlobalRoot>,
+}
+
+impl ProofBundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// reads `keys` from `db` and proves them against its current root
+    pub fn prove(&mut self, tree: &str, kind: ProofKind, db: &dyn TreeDB, keys: Vec<Vec<u8>>) -> ZKResult<()> {
+        let mut kv = Vec::with_capacity(keys.len());
+        for k in keys.iter() {
+            kv.push((k.clone(), db.get(k.as_slice())?.map(Cow::into_owned).unwrap_or_default()));
+        }
+        let mut proof = db.prove(ProveRequest { query: keys })?.proof;
+        if kind == ProofKind::CompactMerk {
+            proof = CompactProof::from_proofs(&[proof])?.encode();
+        }
+        self.entries.push(BundleEntry { tree: tree.to_string(), kind, root: db.root_hash(), version: None, proof, kv });
+        Ok(())
+    }
+
+    /// adds a proof taken elsewhere, typically against an older version of a tree
+    pub fn push(&mut self, entry: BundleEntry) {
+        self.entries.push(entry)
+    }
+
+    /// ties the current entries of the listed trees to the root folded from `trees`
+    pub fn bind(&mut self, trees: Vec<(String, [u8; 32])>) -> [u8; 32] {
+        let root = global_root(trees.as_slice());
+        self.global = Some(GlobalRoot { root, trees });
+        root
+    }
+
+    /// checks every entry against its own root. with a global binding, its root must fold from
+    /// the listed trees and every current entry of a listed tree must use the listed root.
+    /// `expected_global` additionally requires the binding to match a root known to the caller
+    /// and every current entry to belong to a listed tree. historical entries (`version: Some`)
+    /// are bound to nothing, they only prove against the root they carry themselves
+    pub fn verify(&self, expected_global: Option<[u8; 32]>) -> ZKResult<VerifyResponse> {
+        let mut ret = VerifyResponse::default();
+        let bound: HashMap<&str, &[u8; 32]> = match self.global.as_ref() {
+            Some(g) => {
+                if global_root(g.trees.as_slice()) != g.root || expected_global.is_some_and(|r| r != g.root) {
+                    return Ok(ret);
+                }
+                g.trees.iter().map(|(name, root)| (name.as_str(), root)).collect()
+            }
+            None if expected_global.is_some() => return Ok(ret),
+            None => HashMap::new(),
+        };
+        for e in self.entries.iter() {
+            if e.version.is_none() {
+                match bound.get(e.tree.as_str()) {
+                    Some(r) if **r != e.root => return Ok(ret),
+                    None if expected_global.is_some() => return Ok(ret),
+                    _ => {}
+                }
+            }
+            let mut req = VerifyRequest::new(e.proof.clone(), e.root);
+            for (k, v) in e.kv.iter() {
+                req.insert(k.clone(), v.clone());
+            }
+            if !e.kind.verify(req)?.valid {
+                return Ok(ret);
+            }
+        }
+        ret.valid = true;
+        Ok(ret)
+    }
+
+    /// the value proven for `k` in the current entry of `tree`
+    pub fn value(&self, tree: &str, k: &[u8]) -> Option<&[u8]> {
+        self.entries.iter()
+            .filter(|e| e.tree == tree && e.version.is_none())
+            .flat_map(|e| e.kv.iter())
+            .find(|(key, _)| key.as_slice() == k)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    pub fn into_response(self) -> ProveResponse {
+        ProveResponse { proof: self.encode() }
+    }
+
+    pub fn from_response(resp: &ProveResponse) -> ZKResult<Self> {
+        Self::decode(resp.proof.as_slice())
+    }
+}
+
+fn read_root(r: &mut Reader<'_>) -> ZKResult<[u8; 32]> {
+    let mut root = [0u8; 32];
+    root.copy_from_slice(r.take(32)?);
+    Ok(root)
+}
+
+fn read_name(r: &mut Reader<'_>) -> ZKResult<String> {
+    String::from_utf8(r.bytes()?).map_err(|e| ZKError::from(ErrorEnumsStruct::CODEC_INVALID).with_error(Box::new(e)))
+}
+
+impl Codec for ProofBundle {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = header(TAG_PROOF_BUNDLE);
+        buf.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for e in self.entries.iter() {
+            put_bytes(&mut buf, e.tree.as_bytes());
+            buf.push(e.kind.tag());
+            buf.extend_from_slice(&e.root);
+            match e.version {
+                Some(v) => {
+                    buf.push(1);
+                    buf.extend_from_slice(&v.to_be_bytes());
+                }
+                None => buf.push(0),
+            }
+            put_bytes(&mut buf, &e.proof);
+            buf.extend_from_slice(&(e.kv.len() as u32).to_be_bytes());
+            for (k, v) in e.kv.iter() {
+                put_bytes(&mut buf, k);
+                put_bytes(&mut buf, v);
+            }
+        }
+        match self.global.as_ref() {
+            Some(g) => {
+                buf.push(1);
+                buf.extend_from_slice(&g.root);
+                buf.extend_from_slice(&(g.trees.len() as u32).to_be_bytes());
+                for (name, root) in g.trees.iter() {
+                    put_bytes(&mut buf, name.as_bytes());
+                    buf.extend_from_slice(root);
+                }
+            }
+            None => buf.push(0),
+        }
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> ZKResult<Self> {
+        decode_with(bytes, TAG_PROOF_BUNDLE, |r| {
+            let mut ret = ProofBundle::new();
+            for _ in 0..r.u32()? {
+                let tree = read_name(r)?;
+                let kind = ProofKind::from_tag(r.u8()?).ok_or_else(|| r.error())?;
+                let root = read_root(r)?;
+                let version = match r.u8()? {
+                    0 => None,
+                    1 => Some(r.u64()?),
+                    _ => return Err(r.error()),
+                };
+                let proof = r.bytes()?;
+                let mut kv = Vec::new();
+                for _ in 0..r.u32()? {
+                    let k = r.bytes()?;
+                    kv.push((k, r.bytes()?));
+                }
+                ret.entries.push(BundleEntry { tree, kind, root, version, proof, kv });
+            }
+            ret.global = match r.u8()? {
+                0 => None,
+                1 => {
+                    let root = read_root(r)?;
+                    let mut trees = Vec::new();
+                    for _ in 0..r.u32()? {
+                        let name = read_name(r)?;
+                        trees.push((name, read_root(r)?));
+                    }
+                    Some(GlobalRoot { root, trees })
+                }
+                _ => return Err(r.error()),
+            };
+            Ok(ret)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::middleware::builder::HasherType;
+    use crate::tree::bundle::{ProofBundle, ProofKind};
+    use crate::tree::codec::Codec;
+    use crate::tree::hasher::{KeccakHasher, PoseidonHasher};
+    use crate::tree::operation::Operation;
+    use crate::tree::smt::SMTreeDB;
+    use crate::tree::tree::TreeDB;
+
+    #[test]
+    pub fn test_proof_bundle() {
+        let mut accounts = SMTreeDB::<KeccakHasher>::new();
+        let mut orders = SMTreeDB::<PoseidonHasher>::new();
+        accounts.commit(vec![Operation::Set(b"alice".to_vec(), vec![1])]).expect("fail to commit");
+        let mut old = ProofBundle::new();
+        old.prove("accounts", ProofKind::Smt(HasherType::Keccak), &accounts, vec![b"alice".to_vec()]).expect("fail to prove");
+
+        accounts.commit(vec![Operation::Set(b"alice".to_vec(), vec![2])]).expect("fail to commit");
+        orders.commit(vec![Operation::Set(b"o1".to_vec(), vec![9])]).expect("fail to commit");
+
+        let mut bundle = ProofBundle::new();
+        bundle.prove("accounts", ProofKind::Smt(HasherType::Keccak), &accounts, vec![b"alice".to_vec(), b"bob".to_vec()]).expect("fail to prove");
+        bundle.prove("orders", ProofKind::Smt(HasherType::Poseidon), &orders, vec![b"o1".to_vec()]).expect("fail to prove");
+        let mut historical = old.entries.remove(0);
+        historical.version = Some(1);
+        bundle.push(historical);
+        let global = bundle.bind(vec![
+            ("accounts".to_string(), accounts.root_hash()),
+            ("orders".to_string(), orders.root_hash()),
+        ]);
+
+        let bundle = ProofBundle::from_response(&bundle.clone().into_response()).expect("fail to decode");
+        assert!(bundle.verify(Some(global)).unwrap().valid);
+        assert_eq!(bundle.value("accounts", b"alice"), Some(&[2][..]));
+        assert_eq!(bundle.value("accounts", b"bob"), Some(&[][..]));
+        assert!(!bundle.verify(Some([0; 32])).unwrap().valid);
+
+        let mut tampered = bundle.clone();
+        tampered.entries[1].kv[0].1 = vec![8];
+        assert!(!tampered.verify(None).unwrap().valid);
+
+        let mut stale = bundle.clone();
+        stale.entries[2].version = None;
+        assert!(!stale.verify(None).unwrap().valid);
+
+        let mut meta = SMTreeDB::<KeccakHasher>::new();
+        meta.commit(vec![Operation::Set(b"height".to_vec(), vec![7])]).expect("fail to commit");
+        let mut unbound = bundle.clone();
+        unbound.prove("meta", ProofKind::Smt(HasherType::Keccak), &meta, vec![b"height".to_vec()]).expect("fail to prove");
+        assert!(unbound.verify(None).unwrap().valid);
+        assert!(!unbound.verify(Some(global)).unwrap().valid);
+
+        let bytes = bundle.encode();
+        assert!(ProofBundle::decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
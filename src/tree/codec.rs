@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use crate::error::{ErrorEnums, ErrorEnumsStruct, ZKError, ZKResult};
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::operation::Operation;
+
+/// bumped whenever the layout of an encoded message changes, decoding rejects any other version
+pub const CODEC_VERSION: u8 = 1;
+
+const TAG_OPERATION: u8 = 1;
+const TAG_BATCH: u8 = 2;
+const TAG_PROVE_REQUEST: u8 = 3;
+const TAG_PROVE_RESPONSE: u8 = 4;
+const TAG_VERIFY_REQUEST: u8 = 5;
+const TAG_VERIFY_RESPONSE: u8 = 6;
+pub(crate) const TAG_COMPACT_PROOF: u8 = 7;
+pub(crate) const TAG_PROOF_BUNDLE: u8 = 8;
+pub(crate) const TAG_CHUNK_MANIFEST: u8 = 9;
+
+pub(crate) const OP_SET: u8 = 1;
+pub(crate) const OP_DELETE: u8 = 2;
+pub(crate) const OP_INCREMENT: u8 = 3;
+pub(crate) const OP_MERGE: u8 = 4;
+pub(crate) const OP_DELETE_RANGE: u8 = 5;
+
+/// binary encoding of the messages crossing process boundaries.
+/// every message starts with `CODEC_VERSION` and a tag naming its type, integers are big-endian
+/// and byte strings are prefixed with their `u32` length
+pub trait Codec: Sized {
+    fn encode(&self) -> Vec<u8>;
+    fn decode(bytes: &[u8]) -> ZKResult<Self>;
+}
+
+pub(crate) fn put_bytes(buf: &mut Vec<u8>, v: &[u8]) {
+    buf.extend_from_slice(&(v.len() as u32).to_be_bytes());
+    buf.extend_from_slice(v);
+}
+
+pub(crate) fn put_operation(buf: &mut Vec<u8>, op: &Operation) {
+    match op {
+        Operation::Set(k, v) => {
+            buf.push(OP_SET);
+            put_bytes(buf, k);
+            put_bytes(buf, v);
+        }
+        Operation::Delete(k) => {
+            buf.push(OP_DELETE);
+            put_bytes(buf, k);
+        }
+        Operation::Increment(k, delta) => {
+            buf.push(OP_INCREMENT);
+            put_bytes(buf, k);
+            buf.extend_from_slice(&delta.to_be_bytes());
+        }
+        Operation::Merge(k, operand) => {
+            buf.push(OP_MERGE);
+            put_bytes(buf, k);
+            put_bytes(buf, operand);
+        }
+        Operation::DeleteRange(start, end) => {
+            buf.push(OP_DELETE_RANGE);
+            put_bytes(buf, start);
+            put_bytes(buf, end);
+        }
+    }
+}
+
+pub(crate) fn put_operations(buf: &mut Vec<u8>, ops: &[Operation]) {
+    buf.extend_from_slice(&(ops.len() as u32).to_be_bytes());
+    for op in ops {
+        put_operation(buf, op);
+    }
+}
+
+/// cursor over an encoded message, any malformed input fails with `corrupted`
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    corrupted: &'static ErrorEnums,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8], corrupted: &'static ErrorEnums) -> Self {
+        Self { data, offset: 0, corrupted }
+    }
+
+    pub(crate) fn error(&self) -> ZKError {
+        ZKError::from(self.corrupted)
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> ZKResult<&'a [u8]> {
+        if self.offset + n > self.data.len() {
+            return Err(self.error());
+        }
+        let ret = &self.data[self.offset..self.offset + n];
+        self.offset += n;
+        Ok(ret)
+    }
+
+    pub(crate) fn u8(&mut self) -> ZKResult<u8> {
+        self.take(1).map(|v| v[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> ZKResult<u32> {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(b))
+    }
+
+    pub(crate) fn u64(&mut self) -> ZKResult<u64> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(b))
+    }
+
+    pub(crate) fn bytes(&mut self) -> ZKResult<Vec<u8>> {
+        let len = self.u32()? as usize;
+        self.take(len).map(|v| v.to_vec())
+    }
+
+    pub(crate) fn operation(&mut self) -> ZKResult<Operation> {
+        match self.u8()? {
+            OP_SET => {
+                let k = self.bytes()?;
+                Ok(Operation::Set(k, self.bytes()?))
+            }
+            OP_DELETE => Ok(Operation::Delete(self.bytes()?)),
+            OP_INCREMENT => {
+                let k = self.bytes()?;
+                let mut delta = [0u8; 16];
+                delta.copy_from_slice(self.take(16)?);
+                Ok(Operation::Increment(k, i128::from_be_bytes(delta)))
+            }
+            OP_MERGE => {
+                let k = self.bytes()?;
+                Ok(Operation::Merge(k, self.bytes()?))
+            }
+            OP_DELETE_RANGE => {
+                let start = self.bytes()?;
+                Ok(Operation::DeleteRange(start, self.bytes()?))
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    pub(crate) fn operations(&mut self) -> ZKResult<Vec<Operation>> {
+        let count = self.u32()?;
+        let mut ret = Vec::new();
+        for _ in 0..count {
+            ret.push(self.operation()?);
+        }
+        Ok(ret)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offset == self.data.len()
+    }
+
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        let ret = &self.data[self.offset..];
+        self.offset = self.data.len();
+        ret
+    }
+
+    /// trailing bytes mean the message was not what the caller expected
+    pub(crate) fn finish(&self) -> ZKResult<()> {
+        if self.offset != self.data.len() {
+            return Err(self.error());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn header(tag: u8) -> Vec<u8> {
+    vec![CODEC_VERSION, tag]
+}
+
+fn open(bytes: &[u8], tag: u8) -> ZKResult<Reader<'_>> {
+    let mut r = Reader::new(bytes, ErrorEnumsStruct::CODEC_INVALID);
+    let version = r.u8()?;
+    if version != CODEC_VERSION {
+        return Err(ZKError::new(ErrorEnumsStruct::CODEC_INVALID.get_code(),
+                                format!("unsupported codec version {}, expected {}", version, CODEC_VERSION)));
+    }
+    let found = r.u8()?;
+    if found != tag {
+        return Err(ZKError::new(ErrorEnumsStruct::CODEC_INVALID.get_code(),
+                                format!("message tag {}, expected {}", found, tag)));
+    }
+    Ok(r)
+}
+
+pub(crate) fn decode_with<'a, T>(bytes: &'a [u8], tag: u8, f: impl FnOnce(&mut Reader<'a>) -> ZKResult<T>) -> ZKResult<T> {
+    let mut r = open(bytes, tag)?;
+    let ret = f(&mut r)?;
+    r.finish()?;
+    Ok(ret)
+}
+
+impl Codec for Operation {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = header(TAG_OPERATION);
+        put_operation(&mut buf, self);
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> ZKResult<Self> {
+        decode_with(bytes, TAG_OPERATION, |r| r.operation())
+    }
+}
+
+/// a batch of operations, kept in order
+impl Codec for Vec<Operation> {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = header(TAG_BATCH);
+        put_operations(&mut buf, self);
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> ZKResult<Self> {
+        decode_with(bytes, TAG_BATCH, |r| r.operations())
+    }
+}
+
+impl Codec for ProveRequest {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = header(TAG_PROVE_REQUEST);
+        buf.extend_from_slice(&(self.query.len() as u32).to_be_bytes());
+        for k in self.query.iter() {
+            put_bytes(&mut buf, k);
+        }
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> ZKResult<Self> {
+        decode_with(bytes, TAG_PROVE_REQUEST, |r| {
+            let count = r.u32()?;
+            let mut query = Vec::new();
+            for _ in 0..count {
+                query.push(r.bytes()?);
+            }
+            Ok(ProveRequest { query })
+        })
+    }
+}
+
+impl Codec for ProveResponse {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = header(TAG_PROVE_RESPONSE);
+        put_bytes(&mut buf, &self.proof);
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> ZKResult<Self> {
+        decode_with(bytes, TAG_PROVE_RESPONSE, |r| Ok(ProveResponse { proof: r.bytes()? }))
+    }
+}
+
+/// the pairs are written sorted by key, so equal requests always encode to the same bytes
+impl Codec for VerifyRequest {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = header(TAG_VERIFY_REQUEST);
+        put_bytes(&mut buf, &self.proof);
+        buf.extend_from_slice(&self.expected_root);
+        let mut kv: Vec<_> = self.kv.iter().collect();
+        kv.sort();
+        buf.extend_from_slice(&(kv.len() as u32).to_be_bytes());
+        for (k, v) in kv {
+            put_bytes(&mut buf, k);
+            put_bytes(&mut buf, v);
+        }
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> ZKResult<Self> {
+        decode_with(bytes, TAG_VERIFY_REQUEST, |r| {
+            let proof = r.bytes()?;
+            let mut expected_root = [0u8; 32];
+            expected_root.copy_from_slice(r.take(32)?);
+            let count = r.u32()?;
+            let mut kv = HashMap::new();
+            for _ in 0..count {
+                let k = r.bytes()?;
+                if kv.insert(k, r.bytes()?).is_some() {
+                    return Err(r.error());
+                }
+            }
+            Ok(VerifyRequest { proof, expected_root, kv })
+        })
+    }
+}
+
+impl Codec for VerifyResponse {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = header(TAG_VERIFY_RESPONSE);
+        buf.push(self.valid as u8);
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> ZKResult<Self> {
+        decode_with(bytes, TAG_VERIFY_RESPONSE, |r| match r.u8()? {
+            0 => Ok(VerifyResponse { valid: false }),
+            1 => Ok(VerifyResponse { valid: true }),
+            _ => Err(r.error()),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+pub fn to_json<T: serde::Serialize>(v: &T) -> ZKResult<String> {
+    serde_json::to_string(v).map_err(|e| ZKError::from(ErrorEnumsStruct::JSON_SERIALIZE).with_error(Box::new(e)))
+}
+
+#[cfg(feature = "serde")]
+pub fn from_json<T: serde::de::DeserializeOwned>(s: &str) -> ZKResult<T> {
+    serde_json::from_str(s).map_err(|e| ZKError::from(ErrorEnumsStruct::JSON_SERIALIZE).with_error(Box::new(e)))
+}
+
+/// serde adapters writing bytes as hex strings, used through `#[serde(with = ...)]`
+#[cfg(feature = "serde")]
+pub mod hex_serde {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    fn from_hex<E: Error>(s: &str) -> Result<Vec<u8>, E> {
+        hex::decode(s).map_err(E::custom)
+    }
+
+    pub mod bytes {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_str(hex::encode(v).as_str())
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+            from_hex(String::deserialize(d)?.as_str())
+        }
+    }
+
+    pub mod root {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(v: &[u8; 32], s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_str(hex::encode(v).as_str())
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<[u8; 32], D::Error> {
+            let v = from_hex(String::deserialize(d)?.as_str())?;
+            v.try_into().map_err(|v: Vec<u8>| D::Error::custom(format!("root of {} bytes", v.len())))
+        }
+    }
+
+    pub mod list {
+        use super::*;
+        use serde::ser::SerializeSeq;
+
+        pub fn serialize<S: Serializer>(v: &[Vec<u8>], s: S) -> Result<S::Ok, S::Error> {
+            let mut seq = s.serialize_seq(Some(v.len()))?;
+            for item in v {
+                seq.serialize_element(hex::encode(item).as_str())?;
+            }
+            seq.end()
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Vec<u8>>, D::Error> {
+            Vec::<String>::deserialize(d)?.iter().map(|s| from_hex(s.as_str())).collect()
+        }
+    }
+
+    /// written as an object sorted by key
+    pub mod map {
+        use std::collections::{BTreeMap, HashMap};
+        use serde::Serialize;
+        use super::*;
+
+        pub fn serialize<S: Serializer>(v: &HashMap<Vec<u8>, Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+            v.iter().map(|(k, v)| (hex::encode(k), hex::encode(v))).collect::<BTreeMap<_, _>>().serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<HashMap<Vec<u8>, Vec<u8>>, D::Error> {
+            BTreeMap::<String, String>::deserialize(d)?.iter()
+                .map(|(k, v)| Ok((from_hex(k.as_str())?, from_hex(v.as_str())?)))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::ErrorEnumsStruct;
+    use crate::tree::codec::{Codec, CODEC_VERSION};
+    use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+    use crate::tree::operation::Operation;
+
+    fn batch() -> Vec<Operation> {
+        vec![
+            Operation::Set(vec![1], vec![2, 3]),
+            Operation::Delete(vec![4]),
+            Operation::Increment(vec![5], -7),
+            Operation::Merge(vec![6], vec![]),
+            Operation::DeleteRange(vec![7], vec![8]),
+        ]
+    }
+
+    #[test]
+    pub fn test_codec_roundtrip() {
+        let ops = batch();
+        assert_eq!(Vec::<Operation>::decode(&ops.encode()).expect("fail to decode"), ops);
+        for op in ops {
+            assert_eq!(Operation::decode(&op.encode()).expect("fail to decode"), op);
+        }
+
+        let mut prove = ProveRequest::default();
+        prove.insert(vec![1]);
+        prove.insert(vec![]);
+        assert_eq!(ProveRequest::decode(&prove.encode()).expect("fail to decode").query, prove.query);
+        let resp = ProveResponse { proof: vec![9; 40] };
+        assert_eq!(ProveResponse::decode(&resp.encode()).expect("fail to decode").proof, resp.proof);
+
+        let mut verify = VerifyRequest::new(vec![1, 2], [3; 32]);
+        verify.insert(vec![2], vec![2]);
+        verify.insert(vec![1], vec![1]);
+        let decoded = VerifyRequest::decode(&verify.encode()).expect("fail to decode");
+        assert_eq!(decoded.proof, verify.proof);
+        assert_eq!(decoded.expected_root, verify.expected_root);
+        assert_eq!(decoded.kv, verify.kv);
+        assert_eq!(decoded.encode(), verify.encode());
+        assert!(VerifyResponse::decode(&VerifyResponse { valid: true }.encode()).expect("fail to decode").valid);
+    }
+
+    #[test]
+    pub fn test_codec_rejects_malformed() {
+        let mut bytes = batch().encode();
+        let code = ErrorEnumsStruct::CODEC_INVALID.get_code();
+        assert_eq!(Vec::<Operation>::decode(&bytes[..bytes.len() - 1]).unwrap_err().get_code(), code);
+        assert_eq!(Operation::decode(&bytes).unwrap_err().get_code(), code);
+        bytes.push(0);
+        assert_eq!(Vec::<Operation>::decode(&bytes).unwrap_err().get_code(), code);
+        bytes.pop();
+        bytes[0] = CODEC_VERSION + 1;
+        assert_eq!(Vec::<Operation>::decode(&bytes).unwrap_err().get_code(), code);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn test_json_roundtrip() {
+        use crate::tree::codec::{from_json, to_json};
+
+        let ops = batch();
+        let json = to_json(&ops).expect("fail to serialize");
+        assert!(json.contains("\"0203\""));
+        assert_eq!(from_json::<Vec<Operation>>(json.as_str()).expect("fail to deserialize"), ops);
+
+        let mut verify = VerifyRequest::new(vec![1, 2], [3; 32]);
+        verify.insert(vec![0xab], vec![0xcd]);
+        let json = to_json(&verify).expect("fail to serialize");
+        assert!(json.contains("\"ab\":\"cd\""));
+        let decoded: VerifyRequest = from_json(json.as_str()).expect("fail to deserialize");
+        assert_eq!(decoded.kv, verify.kv);
+        assert_eq!(decoded.expected_root, verify.expected_root);
+
+        let err = from_json::<VerifyRequest>("{\"proof\":\"zz\"}").unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::JSON_SERIALIZE.get_code());
+    }
+}
@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 
-#[derive(Default)]
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProveRequest {
+    #[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::list"))]
     pub query: Vec<Vec<u8>>,
 }
 
@@ -12,13 +14,21 @@ impl ProveRequest {
 }
 
 
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProveResponse {
+    #[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))]
     pub proof: Vec<u8>,
 }
 
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VerifyRequest {
+    #[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))]
     pub proof: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::root"))]
     pub expected_root: [u8; 32],
+    #[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::map"))]
     pub kv: HashMap<Vec<u8>, Vec<u8>>,
 }
 
@@ -33,7 +43,8 @@ impl VerifyRequest {
 }
 
 
-#[derive(Default)]
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VerifyResponse {
     pub valid: bool,
 }
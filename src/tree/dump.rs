@@ -0,0 +1,308 @@
+use std::io::{Read, Write};
+use tiny_keccak::{Hasher, Keccak};
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::tree::operation::Operation;
+use crate::tree::tree::{TreeDB, EMPTY_ROOT};
+
+const DUMP_MAGIC: &[u8; 6] = b"ZKDUMP";
+pub const DUMP_VERSION: u8 = 1;
+
+const RECORD_ENTRY: u8 = 1;
+const RECORD_END: u8 = 0;
+
+/// entries committed at once while restoring
+const RESTORE_BATCH: usize = 1024;
+
+fn io_error(e: std::io::Error) -> ZKError {
+    ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e))
+}
+
+fn corrupted(msg: &str) -> ZKError {
+    ZKError::new(ErrorEnumsStruct::DUMP_CORRUPTED.get_code(), msg.to_string())
+}
+
+fn root_mismatch(got: &[u8; 32], expected: &[u8; 32]) -> ZKError {
+    ZKError::new(ErrorEnumsStruct::ROOT_MISMATCH.get_code(),
+                 format!("root {}, expected {}", hex::encode(got), hex::encode(expected)))
+}
+
+/// what a dump holds, as written by `dump` or checked by `restore`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DumpSummary {
+    /// root of the tree the dump was taken from
+    pub root: [u8; 32],
+    pub entries: u64,
+    /// keccak over every byte of the file before it
+    pub checksum: [u8; 32],
+}
+
+/// which root a restored tree must end up with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RootCheck {
+    /// the root recorded in the dump, restoring into the same kind of tree
+    Dump,
+    /// a known root of the target backend, the dump root only holds for the source backend
+    Expected([u8; 32]),
+    Skip,
+}
+
+struct HashingWriter<W: Write> {
+    inner: W,
+    keccak: Keccak,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn put(&mut self, data: &[u8]) -> ZKResult<()> {
+        self.keccak.update(data);
+        self.inner.write_all(data).map_err(io_error)
+    }
+
+    fn put_bytes(&mut self, v: &[u8]) -> ZKResult<()> {
+        self.put(&(v.len() as u32).to_be_bytes())?;
+        self.put(v)
+    }
+}
+
+struct HashingReader<R: Read> {
+    inner: R,
+    keccak: Keccak,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn raw(&mut self, n: usize) -> ZKResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        let read = (&mut self.inner).take(n as u64).read_to_end(&mut buf).map_err(io_error)?;
+        if read != n {
+            return Err(corrupted("dump truncated"));
+        }
+        Ok(buf)
+    }
+
+    fn take(&mut self, n: usize) -> ZKResult<Vec<u8>> {
+        let buf = self.raw(n)?;
+        self.keccak.update(&buf);
+        Ok(buf)
+    }
+
+    fn u8(&mut self) -> ZKResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> ZKResult<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> ZKResult<Vec<u8>> {
+        let len = u32::from_be_bytes(self.take(4)?.try_into().unwrap());
+        self.take(len as usize)
+    }
+
+    fn root(&mut self) -> ZKResult<[u8; 32]> {
+        let mut ret = [0u8; 32];
+        ret.copy_from_slice(&self.take(32)?);
+        Ok(ret)
+    }
+}
+
+/// streams every key/value of `db` to `w`. `root` is the state the caller means to dump,
+/// the dump is refused when `db` has moved away from it
+pub fn dump<D: TreeDB + ?Sized, W: Write>(db: &D, root: [u8; 32], w: W) -> ZKResult<DumpSummary> {
+    if db.root_hash() != root {
+        return Err(root_mismatch(&db.root_hash(), &root));
+    }
+    let mut w = HashingWriter { inner: w, keccak: Keccak::v256() };
+    w.put(DUMP_MAGIC)?;
+    w.put(&[DUMP_VERSION])?;
+    w.put(&root)?;
+    let mut entries = 0u64;
+    db.scan(&[], &[], &mut |k, v| {
+        w.put(&[RECORD_ENTRY])?;
+        w.put_bytes(k)?;
+        w.put_bytes(v)?;
+        entries += 1;
+        Ok(())
+    })?;
+    w.put(&[RECORD_END])?;
+    w.put(&entries.to_be_bytes())?;
+    let mut checksum = [0u8; 32];
+    w.keccak.finalize(&mut checksum);
+    w.inner.write_all(&checksum).map_err(io_error)?;
+    w.inner.flush().map_err(io_error)?;
+    Ok(DumpSummary { root, entries, checksum })
+}
+
+/// restores a dump into an empty `db`, committing the entries in batches while they are read and
+/// comparing the resulting root according to `check`. on any error, a corrupted dump included,
+/// whatever was committed is deleted again so `db` is left empty
+pub fn restore<D: TreeDB + ?Sized, R: Read>(db: &mut D, r: R, check: RootCheck) -> ZKResult<DumpSummary> {
+    if db.root_hash() != EMPTY_ROOT {
+        return Err(ZKError::new(ErrorEnumsStruct::ROOT_MISMATCH.get_code(),
+                                format!("restore target is not empty,root {}", hex::encode(db.root_hash()))));
+    }
+    let ret = restore_entries(db, r, check);
+    if ret.is_err() && db.root_hash() != EMPTY_ROOT {
+        if let Err(e) = db.commit(vec![Operation::DeleteRange(vec![], vec![])]) {
+            return ret.map_err(|err| err.with_wrapped_error(Box::new(e)));
+        }
+    }
+    ret
+}
+
+fn restore_entries<D: TreeDB + ?Sized, R: Read>(db: &mut D, r: R, check: RootCheck) -> ZKResult<DumpSummary> {
+    let mut r = HashingReader { inner: r, keccak: Keccak::v256() };
+    if r.take(DUMP_MAGIC.len())? != DUMP_MAGIC {
+        return Err(corrupted("not a dump"));
+    }
+    if r.u8()? != DUMP_VERSION {
+        return Err(corrupted("unsupported dump version"));
+    }
+    let root = r.root()?;
+    let mut batch = Vec::with_capacity(RESTORE_BATCH);
+    let mut read = 0u64;
+    let mut last: Option<Vec<u8>> = None;
+    loop {
+        match r.u8()? {
+            RECORD_ENTRY => {
+                let k = r.bytes()?;
+                let v = r.bytes()?;
+                if last.as_ref().is_some_and(|l| *l >= k) {
+                    return Err(corrupted("dump keys out of order"));
+                }
+                last = Some(k.clone());
+                batch.push(Operation::Set(k, v));
+                read += 1;
+                if batch.len() == RESTORE_BATCH {
+                    db.commit(std::mem::take(&mut batch))?;
+                }
+            }
+            RECORD_END => break,
+            _ => return Err(corrupted("unknown dump record")),
+        }
+    }
+    let entries = r.u64()?;
+    let stored = r.raw(32)?;
+    let mut checksum = [0u8; 32];
+    r.keccak.finalize(&mut checksum);
+    if stored != checksum {
+        return Err(corrupted("dump checksum mismatch"));
+    }
+    if entries != read {
+        return Err(corrupted("dump entry count mismatch"));
+    }
+    let mut rest = [0u8; 1];
+    if r.inner.read(&mut rest).map_err(io_error)? != 0 {
+        return Err(corrupted("trailing bytes after dump"));
+    }
+
+    db.commit(batch)?;
+    let expected = match check {
+        RootCheck::Dump => Some(root),
+        RootCheck::Expected(expected) => Some(expected),
+        RootCheck::Skip => None,
+    };
+    if let Some(expected) = expected {
+        if db.root_hash() != expected {
+            return Err(root_mismatch(&db.root_hash(), &expected));
+        }
+    }
+    Ok(DumpSummary { root, entries, checksum })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::ErrorEnumsStruct;
+    use crate::tree::dump::{dump, restore, RootCheck};
+    use crate::tree::hasher::KeccakHasher;
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::operation::Operation;
+    use crate::tree::smt::SMTreeDB;
+    use crate::tree::tree::{DB, TreeDB};
+
+    fn ops() -> Vec<Operation> {
+        (0..50u32).map(|i| Operation::Set(i.to_be_bytes().to_vec(), vec![i as u8; (i % 7) as usize + 1])).collect()
+    }
+
+    #[test]
+    pub fn test_dump_restore() {
+        let mut source = MemoryTreeDB::new();
+        source.commit(ops()).expect("fail to commit");
+        let mut file = Vec::new();
+        let summary = dump(&source, source.root_hash(), &mut file).expect("fail to dump");
+        assert_eq!(summary.entries, 50);
+
+        let mut replica = MemoryTreeDB::new();
+        assert_eq!(restore(&mut replica, file.as_slice(), RootCheck::Dump).expect("fail to restore"), summary);
+        assert_eq!(replica.root_hash(), source.root_hash());
+        assert_eq!(replica.get(&7u32.to_be_bytes()).unwrap(), source.get(&7u32.to_be_bytes()).unwrap());
+
+        let err = dump(&source, [0; 32], &mut Vec::new()).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::ROOT_MISMATCH.get_code());
+
+        let root = replica.root_hash();
+        let err = restore(&mut replica, file.as_slice(), RootCheck::Skip).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::ROOT_MISMATCH.get_code());
+        assert_eq!(replica.root_hash(), root);
+    }
+
+    #[test]
+    pub fn test_restore_in_batches() {
+        let mut source = MemoryTreeDB::new();
+        source.commit((0..3000u32).map(|i| Operation::Set(i.to_be_bytes().to_vec(), vec![1])).collect()).expect("fail to commit");
+        let mut file = Vec::new();
+        dump(&source, source.root_hash(), &mut file).expect("fail to dump");
+
+        let mut replica = MemoryTreeDB::new();
+        assert_eq!(restore(&mut replica, file.as_slice(), RootCheck::Skip).expect("fail to restore").entries, 3000);
+        assert_eq!(replica.range(&[], &[]).unwrap(), source.range(&[], &[]).unwrap());
+
+        let mut forged = file.clone();
+        let at = forged.len() - 40;
+        forged[at] ^= 1;
+        let mut db = MemoryTreeDB::new();
+        let err = restore(&mut db, forged.as_slice(), RootCheck::Skip).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::DUMP_CORRUPTED.get_code());
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    pub fn test_restore_across_backends() {
+        let mut smt = SMTreeDB::<KeccakHasher>::new();
+        smt.commit(ops()).expect("fail to commit");
+        let mut file = Vec::new();
+        dump(&smt, smt.root_hash(), &mut file).expect("fail to dump");
+
+        let mut memory = MemoryTreeDB::new();
+        let err = restore(&mut memory, file.as_slice(), RootCheck::Dump).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::ROOT_MISMATCH.get_code());
+        assert!(memory.is_empty());
+
+        let mut expected = MemoryTreeDB::new();
+        expected.commit(ops()).expect("fail to commit");
+        restore(&mut memory, file.as_slice(), RootCheck::Expected(expected.root_hash())).expect("fail to restore");
+        assert_eq!(memory.range(&[], &[]).unwrap(), smt.range(&[], &[]).unwrap());
+
+        let mut back = SMTreeDB::<KeccakHasher>::new();
+        let mut again = Vec::new();
+        dump(&memory, memory.root_hash(), &mut again).expect("fail to dump");
+        restore(&mut back, again.as_slice(), RootCheck::Expected(smt.root_hash())).expect("fail to restore");
+    }
+
+    #[test]
+    pub fn test_restore_rejects_corruption() {
+        let mut source = MemoryTreeDB::new();
+        source.commit(ops()).expect("fail to commit");
+        let mut file = Vec::new();
+        dump(&source, source.root_hash(), &mut file).expect("fail to dump");
+
+        let corrupted = ErrorEnumsStruct::DUMP_CORRUPTED.get_code();
+        for at in [0, 10, file.len() / 2, file.len() - 1] {
+            let mut forged = file.clone();
+            forged[at] ^= 1;
+            let mut db = MemoryTreeDB::new();
+            assert_eq!(restore(&mut db, forged.as_slice(), RootCheck::Skip).unwrap_err().get_code(), corrupted);
+            assert!(db.range(&[], &[]).unwrap().is_empty());
+        }
+        let err = restore(&mut MemoryTreeDB::new(), &file[..file.len() - 5], RootCheck::Skip).unwrap_err();
+        assert_eq!(err.get_code(), corrupted);
+    }
+}
@@ -0,0 +1,198 @@
+use hash256_std_hasher::Hash256StdHasher;
+use hash_db::Hasher;
+use tiny_keccak::{Hasher as KeccaHasher, Keccak};
+use crate::tree::poseidon::{hash2, hash_bytes, Fr};
+
+/// hash function used by the tree layer, every tree node is 32 bytes
+pub trait TreeHasher: Default + Clone + Send + Sync + 'static {
+    const NAME: &'static str;
+
+    fn hash(data: &[u8]) -> [u8; 32];
+
+    /// hash of an inner node
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut buf = [0u8; 64];
+        buf[..32].copy_from_slice(left);
+        buf[32..].copy_from_slice(right);
+        Self::hash(&buf)
+    }
+}
+
+pub type KeccakHash = [u8; 32];
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct KeccakHasher;
+
+impl Hasher for KeccakHasher {
+    type Out = KeccakHash;
+
+    type StdHasher = Hash256StdHasher;
+
+    const LENGTH: usize = 32;
+
+    fn hash(x: &[u8]) -> Self::Out {
+        let mut keccak = Keccak::v256();
+        keccak.update(x);
+        let mut out = [0u8; 32];
+        keccak.finalize(&mut out);
+        out
+    }
+}
+
+impl TreeHasher for KeccakHasher {
+    const NAME: &'static str = "keccak256";
+
+    fn hash(data: &[u8]) -> [u8; 32] {
+        <KeccakHasher as Hasher>::hash(data)
+    }
+}
+
+/// blake2b with a 32 byte digest
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Blake2bHasher;
+
+impl TreeHasher for Blake2bHasher {
+    const NAME: &'static str = "blake2b256";
+
+    fn hash(data: &[u8]) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        out.copy_from_slice(blake2b(data, 32).as_slice());
+        out
+    }
+}
+
+/// poseidon over the BN254 scalar field, nodes are field elements in big endian.
+/// inner nodes are circomlib's `Poseidon(2)` so a circuit can recompute paths cheaply
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct PoseidonHasher;
+
+impl TreeHasher for PoseidonHasher {
+    const NAME: &'static str = "poseidon_bn254";
+
+    fn hash(data: &[u8]) -> [u8; 32] {
+        hash_bytes(data).to_be_bytes()
+    }
+
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        hash2(&Fr::from_be_bytes(left), &Fr::from_be_bytes(right)).to_be_bytes()
+    }
+}
+
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+const BLAKE2B_SIGMA: [[usize; 16]; 12] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+];
+
+fn blake2b_compress(h: &mut [u64; 8], block: &[u8; 128], t: u128, last: bool) {
+    let mut m = [0u64; 16];
+    for (i, w) in m.iter_mut().enumerate() {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&block[i * 8..i * 8 + 8]);
+        *w = u64::from_le_bytes(b);
+    }
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(h);
+    v[8..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= t as u64;
+    v[13] ^= (t >> 64) as u64;
+    if last {
+        v[14] = !v[14];
+    }
+
+    fn g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+        v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+        v[d] = (v[d] ^ v[a]).rotate_right(32);
+        v[c] = v[c].wrapping_add(v[d]);
+        v[b] = (v[b] ^ v[c]).rotate_right(24);
+        v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+        v[d] = (v[d] ^ v[a]).rotate_right(16);
+        v[c] = v[c].wrapping_add(v[d]);
+        v[b] = (v[b] ^ v[c]).rotate_right(63);
+    }
+
+    for s in BLAKE2B_SIGMA.iter() {
+        g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+        g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+        g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+        g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+        g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+        g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+        g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+        g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+    }
+    for i in 0..8 {
+        h[i] ^= v[i] ^ v[i + 8];
+    }
+}
+
+/// unkeyed blake2b (RFC 7693) with a digest of `out_len` bytes
+fn blake2b(data: &[u8], out_len: usize) -> Vec<u8> {
+    let mut h = BLAKE2B_IV;
+    h[0] ^= 0x01010000 ^ out_len as u64;
+
+    let mut offset = 0;
+    while data.len() - offset > 128 {
+        let mut block = [0u8; 128];
+        block.copy_from_slice(&data[offset..offset + 128]);
+        offset += 128;
+        blake2b_compress(&mut h, &block, offset as u128, false);
+    }
+    let mut block = [0u8; 128];
+    block[..data.len() - offset].copy_from_slice(&data[offset..]);
+    blake2b_compress(&mut h, &block, data.len() as u128, true);
+
+    let mut out = Vec::with_capacity(64);
+    for w in h.iter() {
+        out.extend_from_slice(&w.to_le_bytes());
+    }
+    out.truncate(out_len);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree::hasher::{blake2b, Blake2bHasher, KeccakHasher, PoseidonHasher, TreeHasher};
+    use crate::tree::poseidon::{hash2, Fr};
+
+    #[test]
+    pub fn test_keccak() {
+        assert_eq!(hex::encode(<KeccakHasher as TreeHasher>::hash(b"")),
+                   "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+    }
+
+    #[test]
+    pub fn test_blake2b() {
+        assert_eq!(hex::encode(blake2b(b"abc", 64)),
+                   "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923");
+        assert_eq!(hex::encode(Blake2bHasher::hash(b"abc")),
+                   "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319");
+        let long = vec![7u8; 300];
+        assert_ne!(Blake2bHasher::hash(&long[..256]), Blake2bHasher::hash(&long));
+    }
+
+    #[test]
+    pub fn test_poseidon_pair() {
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let mut two = [0u8; 32];
+        two[31] = 2;
+        // the field vector itself is checked in poseidon
+        assert_eq!(PoseidonHasher::hash_pair(&one, &two), hash2(&Fr::from_u64(1), &Fr::from_u64(2)).to_be_bytes());
+        assert_ne!(PoseidonHasher::hash(b"abc"), PoseidonHasher::hash(b"abd"));
+    }
+}
@@ -0,0 +1,622 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ops::Bound;
+use merk::Hash;
+use merk::proofs::{encode_into, Node as ProofNode, Op as ProofOp};
+use merk::tree::{kv_hash, node_hash, NULL_HASH};
+use crate::error::{ErrorEnums, ErrorEnumsStruct, ZKError, ZKResult};
+use crate::tree::codec::{put_bytes, Reader};
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::merkle::verify_merk_proof;
+use crate::tree::operation::{resolve, Operation};
+use crate::tree::tree::{range_bounds, Visitor, DB, TreeDB};
+
+struct Node {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    kv_hash: Hash,
+    hash: Hash,
+    height: u8,
+    left: Option<Box<Node>>,
+    right: Option<Box<Node>>,
+    /// changed since the last `take_dirty`
+    dirty: bool,
+}
+
+/// a sorted batch holding each key once, `None` deletes the key
+type Batch = [(Vec<u8>, Option<Vec<u8>>)];
+
+/// the batch apply of merk (`apply_to`, `build`, `recurse`, `maybe_balance`, `rotate`, `remove`),
+/// replicated step by step so the shape of the tree and therefore the root follow merk after
+/// any sequence of batches
+impl Node {
+    fn new(key: Vec<u8>, value: Vec<u8>) -> Box<Node> {
+        let kv = kv_hash(key.as_slice(), value.as_slice());
+        Box::new(Node { key, value, kv_hash: kv, hash: node_hash(&kv, &NULL_HASH, &NULL_HASH), height: 1, left: None, right: None, dirty: true })
+    }
+
+    fn child_hash(child: &Option<Box<Node>>) -> Hash {
+        child.as_ref().map_or(NULL_HASH, |c| c.hash)
+    }
+
+    fn child(&self, left: bool) -> &Option<Box<Node>> {
+        if left { &self.left } else { &self.right }
+    }
+
+    fn child_mut(&mut self, left: bool) -> &mut Option<Box<Node>> {
+        if left { &mut self.left } else { &mut self.right }
+    }
+
+    fn child_height(&self, left: bool) -> u8 {
+        self.child(left).as_ref().map_or(0, |c| c.height)
+    }
+
+    fn balance_factor(&self) -> i8 {
+        self.child_height(false) as i8 - self.child_height(true) as i8
+    }
+
+    fn update(&mut self) {
+        self.height = 1 + self.child_height(true).max(self.child_height(false));
+        self.hash = node_hash(&self.kv_hash, &Node::child_hash(&self.left), &Node::child_hash(&self.right));
+        self.dirty = true;
+    }
+
+    fn with_value(mut self: Box<Self>, value: Vec<u8>) -> Box<Node> {
+        self.kv_hash = kv_hash(self.key.as_slice(), value.as_slice());
+        self.value = value;
+        self.update();
+        self
+    }
+
+    fn attach(mut self: Box<Self>, left: bool, child: Option<Box<Node>>) -> Box<Node> {
+        *self.child_mut(left) = child;
+        self.update();
+        self
+    }
+
+    /// the node is attached somewhere again afterwards, which brings its hash up to date
+    fn detach(mut self: Box<Self>, left: bool) -> (Box<Node>, Option<Box<Node>>) {
+        let child = self.child_mut(left).take();
+        (self, child)
+    }
+
+    fn detach_expect(self: Box<Self>, left: bool) -> (Box<Node>, Box<Node>) {
+        let (node, child) = self.detach(left);
+        (node, child.expect("child is present"))
+    }
+
+    fn walk<F: FnOnce(Option<Box<Node>>) -> Option<Box<Node>>>(self: Box<Self>, left: bool, f: F) -> Box<Node> {
+        let (node, child) = self.detach(left);
+        node.attach(left, f(child))
+    }
+
+    fn apply_sorted(self: Box<Self>, batch: &Batch) -> Option<Box<Node>> {
+        let search = batch.binary_search_by(|(k, _)| k.as_slice().cmp(self.key.as_slice()));
+        let node = match search {
+            Ok(i) => match &batch[i].1 {
+                Some(v) => self.with_value(v.clone()),
+                None => {
+                    let rest = apply_to(self.remove(), &batch[..i]);
+                    return apply_to(rest, &batch[i + 1..]);
+                }
+            },
+            Err(_) => self,
+        };
+        let (mid, exclusive) = match search {
+            Ok(i) => (i, true),
+            Err(i) => (i, false),
+        };
+        Some(node.recurse(batch, mid, exclusive))
+    }
+
+    fn recurse(self: Box<Self>, batch: &Batch, mid: usize, exclusive: bool) -> Box<Node> {
+        let left_batch = &batch[..mid];
+        let right_batch = if exclusive { &batch[mid + 1..] } else { &batch[mid..] };
+        let mut node = self;
+        if !left_batch.is_empty() {
+            node = node.walk(true, |c| apply_to(c, left_batch));
+        }
+        if !right_batch.is_empty() {
+            node = node.walk(false, |c| apply_to(c, right_batch));
+        }
+        node.maybe_balance()
+    }
+
+    fn maybe_balance(self: Box<Self>) -> Box<Node> {
+        let balance_factor = self.balance_factor();
+        if balance_factor.abs() <= 1 {
+            return self;
+        }
+        let left = balance_factor < 0;
+        let double = left == (self.child(left).as_ref().expect("taller side has a child").balance_factor() > 0);
+        let node = if double {
+            self.walk(left, |c| c.map(|c| c.rotate(!left)))
+        } else {
+            self
+        };
+        node.rotate(left)
+    }
+
+    fn rotate(self: Box<Self>, left: bool) -> Box<Node> {
+        let (node, child) = self.detach_expect(left);
+        let (child, grandchild) = child.detach(!left);
+        let node = node.attach(left, grandchild).maybe_balance();
+        child.attach(!left, Some(node)).maybe_balance()
+    }
+
+    fn remove(self: Box<Self>) -> Option<Box<Node>> {
+        let (has_left, has_right) = (self.left.is_some(), self.right.is_some());
+        let left = self.child_height(true) > self.child_height(false);
+        if has_left && has_right {
+            let (node, tall) = self.detach_expect(left);
+            let (_, short) = node.detach_expect(!left);
+            Some(tall.promote_edge(!left, short))
+        } else if has_left || has_right {
+            Some(self.detach_expect(left).1)
+        } else {
+            None
+        }
+    }
+
+    fn promote_edge(self: Box<Self>, left: bool, attach: Box<Node>) -> Box<Node> {
+        let (edge, child) = self.remove_edge(left);
+        edge.attach(!left, child).attach(left, Some(attach)).maybe_balance()
+    }
+
+    fn remove_edge(self: Box<Self>, left: bool) -> (Box<Node>, Option<Box<Node>>) {
+        if self.child(left).is_some() {
+            let (node, child) = self.detach_expect(left);
+            let (edge, rest) = child.remove_edge(left);
+            (edge, Some(node.attach(left, rest).maybe_balance()))
+        } else {
+            self.detach(!left)
+        }
+    }
+}
+
+/// a node as stored outside of memory: which children it has, their keys and the value.
+/// hashes and heights are recomputed when it is loaded
+impl Node {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![self.left.is_some() as u8 | (self.right.is_some() as u8) << 1];
+        for child in [&self.left, &self.right].into_iter().flatten() {
+            put_bytes(&mut buf, child.key.as_slice());
+        }
+        put_bytes(&mut buf, self.value.as_slice());
+        buf
+    }
+
+    /// `bounds` are the keys of the nearest ancestors on either side, a child outside of them
+    /// would make the stored nodes a cycle instead of a tree
+    fn load(key: &[u8], bounds: (Option<&[u8]>, Option<&[u8]>), fetch: &mut Fetch, corrupted: &'static ErrorEnums) -> ZKResult<(Box<Node>, usize)> {
+        if bounds.0.is_some_and(|lo| key <= lo) || bounds.1.is_some_and(|hi| key >= hi) {
+            return Err(ZKError::from(corrupted));
+        }
+        let bytes = fetch(key)?.ok_or_else(|| ZKError::from(corrupted))?;
+        let mut r = Reader::new(bytes.as_slice(), corrupted);
+        let flags = r.u8()?;
+        if flags > 3 {
+            return Err(r.error());
+        }
+        let left_key = if flags & 1 != 0 { Some(r.bytes()?) } else { None };
+        let right_key = if flags & 2 != 0 { Some(r.bytes()?) } else { None };
+        let value = r.bytes()?;
+        r.finish()?;
+
+        let mut node = Node::new(key.to_vec(), value);
+        let mut count = 1;
+        if let Some(k) = left_key {
+            let (child, n) = Node::load(k.as_slice(), (bounds.0, Some(key)), fetch, corrupted)?;
+            node.left = Some(child);
+            count += n;
+        }
+        if let Some(k) = right_key {
+            let (child, n) = Node::load(k.as_slice(), (Some(key), bounds.1), fetch, corrupted)?;
+            node.right = Some(child);
+            count += n;
+        }
+        node.update();
+        node.dirty = false;
+        Ok((node, count))
+    }
+
+    fn take_dirty(&mut self, f: &mut dyn FnMut(&[u8], Vec<u8>)) {
+        if !self.dirty {
+            return;
+        }
+        f(self.key.as_slice(), self.encode());
+        self.dirty = false;
+        for child in [&mut self.left, &mut self.right].into_iter().flatten() {
+            child.take_dirty(f);
+        }
+    }
+}
+
+/// reads a stored node by its key
+pub(crate) type Fetch<'a> = dyn FnMut(&[u8]) -> ZKResult<Option<Vec<u8>>> + 'a;
+
+fn apply_to(tree: Option<Box<Node>>, batch: &Batch) -> Option<Box<Node>> {
+    if batch.is_empty() {
+        return tree;
+    }
+    match tree {
+        Some(node) => node.apply_sorted(batch),
+        None => build(batch),
+    }
+}
+
+/// deletes never reach an empty subtree, `commit` drops deletes of missing keys like merk refuses them
+fn build(batch: &Batch) -> Option<Box<Node>> {
+    let mid = batch.len() / 2;
+    let (key, value) = &batch[mid];
+    let value = value.clone().expect("deletes of missing keys are dropped");
+    Some(Node::new(key.clone(), value).recurse(batch, mid, true))
+}
+
+fn visit_range(node: &Option<Box<Node>>, bounds: &(Bound<&[u8]>, Bound<&[u8]>), f: &mut Visitor) -> ZKResult<()> {
+    let node = match node {
+        Some(node) => node,
+        None => return Ok(()),
+    };
+    let k = node.key.as_slice();
+    let above_start = match bounds.0 {
+        Bound::Included(s) => k >= s,
+        Bound::Excluded(s) => k > s,
+        Bound::Unbounded => true,
+    };
+    let below_end = match bounds.1 {
+        Bound::Included(e) => k <= e,
+        Bound::Excluded(e) => k < e,
+        Bound::Unbounded => true,
+    };
+    if above_start {
+        visit_range(&node.left, bounds, f)?;
+    }
+    if above_start && below_end {
+        f(node.key.as_slice(), node.value.as_slice())?;
+    }
+    if below_end {
+        visit_range(&node.right, bounds, f)?;
+    }
+    Ok(())
+}
+
+/// pure in-memory tree using merk's node hashing and proof encoding,
+/// proofs verify with `merk::verify` and the other way round.
+/// writes are applied in place the way merk applies a batch, so the root matches a merk store
+/// that saw the same batches
+#[derive(Default)]
+pub struct MemoryTreeDB {
+    root: Option<Box<Node>>,
+    len: usize,
+}
+
+impl MemoryTreeDB {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn find(&self, k: &[u8]) -> Option<&Node> {
+        let mut cur = self.root.as_deref();
+        while let Some(node) = cur {
+            cur = match k.cmp(node.key.as_slice()) {
+                Ordering::Equal => return Some(node),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    /// rebuilds a tree handed out by `take_dirty`, starting at the node stored under `root`.
+    /// malformed nodes fail with `corrupted`
+    pub(crate) fn load(root: Option<&[u8]>, fetch: &mut Fetch, corrupted: &'static ErrorEnums) -> ZKResult<Self> {
+        match root {
+            Some(k) => {
+                let (node, len) = Node::load(k, (None, None), fetch, corrupted)?;
+                Ok(Self { root: Some(node), len })
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub(crate) fn root_key(&self) -> Option<&[u8]> {
+        self.root.as_ref().map(|r| r.key.as_slice())
+    }
+
+    /// passes every node changed since the last call to `f`, encoded for `load`. a changed node
+    /// marks its ancestors changed too, so the walk stops at the first unchanged one
+    pub(crate) fn take_dirty(&mut self, f: &mut dyn FnMut(&[u8], Vec<u8>)) {
+        if let Some(root) = self.root.as_mut() {
+            root.take_dirty(f);
+        }
+    }
+
+    /// applies resolved operations as one merk batch
+    pub(crate) fn apply(&mut self, operations: Vec<Operation>) {
+        let mut batch = Vec::with_capacity(operations.len());
+        for op in operations {
+            let exists = self.find(op.key()).is_some();
+            match op {
+                Operation::Set(k, v) => {
+                    if !exists {
+                        self.len += 1;
+                    }
+                    batch.push((k, Some(v)));
+                }
+                Operation::Delete(k) if exists => {
+                    self.len -= 1;
+                    batch.push((k, None));
+                }
+                Operation::Delete(_) => {}
+                _ => unreachable!("operations are resolved before they are applied"),
+            }
+        }
+        self.root = apply_to(self.root.take(), batch.as_slice());
+    }
+}
+
+/// same walk as merk's `create_proof`, the flags tell whether a queried key is missing beyond the
+/// left/right edge of the subtree, in which case the neighbouring node has to be revealed
+fn create_proof(node: &Node, query: &[Vec<u8>]) -> (Vec<ProofOp>, (bool, bool)) {
+    let search = query.binary_search_by(|k| k.as_slice().cmp(node.key.as_slice()));
+    let (left_items, right_items) = match search {
+        Ok(i) => (&query[..i], &query[i + 1..]),
+        Err(i) => (&query[..i], &query[i..]),
+    };
+    let (mut proof, left_absence) = create_child_proof(&node.left, left_items);
+    let (mut right_proof, right_absence) = create_child_proof(&node.right, right_items);
+    let (has_left, has_right) = (!proof.is_empty(), !right_proof.is_empty());
+
+    if search.is_ok() || left_absence.1 || right_absence.0 {
+        proof.push(ProofOp::Push(ProofNode::KV(node.key.clone(), node.value.clone())));
+    } else {
+        proof.push(ProofOp::Push(ProofNode::KVHash(node.kv_hash)));
+    }
+    if has_left {
+        proof.push(ProofOp::Parent);
+    }
+    if has_right {
+        proof.append(&mut right_proof);
+        proof.push(ProofOp::Child);
+    }
+    (proof, (left_absence.0, right_absence.1))
+}
+
+fn create_child_proof(child: &Option<Box<Node>>, query: &[Vec<u8>]) -> (Vec<ProofOp>, (bool, bool)) {
+    match child {
+        Some(c) if !query.is_empty() => create_proof(c, query),
+        None if !query.is_empty() => (vec![], (true, true)),
+        Some(c) => (vec![ProofOp::Push(ProofNode::Hash(c.hash))], (false, false)),
+        None => (vec![], (false, false)),
+    }
+}
+
+impl DB for MemoryTreeDB {
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        Ok(self.find(k).map(|n| Cow::Borrowed(n.value.as_slice())))
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        let old = self.find(k).map(|n| n.value.clone());
+        self.apply(vec![Operation::Set(k.to_vec(), v)]);
+        Ok(old)
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        let old = self.find(k).map(|n| n.value.clone());
+        self.apply(vec![Operation::Delete(k.to_vec())]);
+        Ok(old)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut ret = Vec::new();
+        self.scan(start, end, &mut |k, v| {
+            ret.push((k.to_vec(), v.to_vec()));
+            Ok(())
+        })?;
+        Ok(ret)
+    }
+
+    fn scan(&self, start: &[u8], end: &[u8], f: &mut Visitor) -> ZKResult<()> {
+        match range_bounds(start, end) {
+            Some(bounds) => visit_range(&self.root, &bounds, f),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TreeDB for MemoryTreeDB {
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        let root = self.root.as_ref().ok_or_else(|| {
+            ZKError::new(ErrorEnumsStruct::UNKNOWN.get_code(), "can not create proof for empty tree".to_string())
+        })?;
+        let mut query = req.query;
+        query.sort();
+        query.dedup();
+        let (ops, _) = create_proof(root, query.as_slice());
+        let mut proof = Vec::with_capacity(128);
+        encode_into(ops.iter(), &mut proof);
+        Ok(ProveResponse { proof })
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        verify_merk_proof(req)
+    }
+
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        let operations = resolve(self, operations)?;
+        self.apply(operations);
+        Ok(())
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.root.as_ref().map_or(NULL_HASH, |r| r.hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use merk::{Merk, Op};
+    use merk::tree::{kv_hash, node_hash};
+    use crate::error::ErrorEnumsStruct;
+    use crate::tree::couple::{ProveRequest, VerifyRequest};
+    use crate::tree::memory::{MemoryTreeDB, Node};
+    use crate::tree::operation::{resolve, Operation};
+    use crate::tree::tree::{DB, TreeDB};
+
+    #[test]
+    pub fn test_root_matches_merk() {
+        let mut mem = MemoryTreeDB::new();
+        let mut merk = Merk::open("./merk_memory_compat.db").unwrap();
+        let mut batch = vec![];
+        let mut ops = vec![];
+        for i in 0..20u8 {
+            batch.push((vec![i], Op::Put(vec![i, i])));
+            ops.push(Operation::Set(vec![i], vec![i, i]));
+        }
+        merk.apply(&batch, &[]).expect("fail to apply");
+        mem.commit(ops).expect("fail to commit");
+        assert_eq!(mem.root_hash(), merk.root_hash());
+
+        for round in 0..30u8 {
+            let mut batch = vec![];
+            let mut ops = vec![];
+            for i in (round % 5..60).step_by(7) {
+                let k = vec![i.wrapping_mul(round | 1)];
+                if mem.get(&k).unwrap().is_some() && i % 3 == 0 {
+                    batch.push((k.clone(), Op::Delete));
+                    ops.push(Operation::Delete(k));
+                } else {
+                    batch.push((k.clone(), Op::Put(vec![round])));
+                    ops.push(Operation::Set(k, vec![round]));
+                }
+            }
+            batch.sort_by(|a, b| a.0.cmp(&b.0));
+            batch.dedup_by(|a, b| a.0 == b.0);
+            merk.apply(&batch, &[]).expect("fail to apply");
+            mem.commit(ops).expect("fail to commit");
+            assert_eq!(mem.root_hash(), merk.root_hash());
+        }
+        merk.destroy().unwrap();
+    }
+
+    fn check_node(node: &Option<Box<Node>>) -> u8 {
+        let node = match node {
+            Some(node) => node,
+            None => return 0,
+        };
+        let (left, right) = (check_node(&node.left), check_node(&node.right));
+        assert!((right as i8 - left as i8).abs() <= 1);
+        assert_eq!(node.height, 1 + left.max(right));
+        assert_eq!(node.kv_hash, kv_hash(&node.key, &node.value));
+        assert_eq!(node.hash, node_hash(&node.kv_hash, &Node::child_hash(&node.left), &Node::child_hash(&node.right)));
+        node.height
+    }
+
+    #[test]
+    pub fn test_incremental_updates() {
+        let mut mem = MemoryTreeDB::new();
+        let mut model = BTreeMap::new();
+        for round in 0..200u32 {
+            let k = (round * 37 % 101).to_be_bytes().to_vec();
+            if round % 3 == 0 {
+                assert_eq!(mem.delete(&k).unwrap(), model.remove(&k));
+            } else {
+                assert_eq!(mem.set(&k, round.to_be_bytes().to_vec()).unwrap(), model.insert(k, round.to_be_bytes().to_vec()));
+            }
+            check_node(&mem.root);
+        }
+        mem.commit(vec![Operation::DeleteRange(20u32.to_be_bytes().to_vec(), 70u32.to_be_bytes().to_vec())]).expect("fail to commit");
+        model.retain(|k, _| k.as_slice() < &20u32.to_be_bytes()[..] || k.as_slice() >= &70u32.to_be_bytes()[..]);
+        check_node(&mem.root);
+
+        assert_eq!(mem.len(), model.len());
+        let all: Vec<(Vec<u8>, Vec<u8>)> = model.into_iter().collect();
+        assert_eq!(mem.range(&[], &[]).unwrap(), all);
+        assert_eq!(mem.range(&20u32.to_be_bytes(), &[]).unwrap(), all.into_iter().filter(|(k, _)| k.as_slice() >= &70u32.to_be_bytes()[..]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn test_load_persisted_nodes() {
+        let mut mem = MemoryTreeDB::new();
+        let mut stored: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        for round in 0..20u8 {
+            let mut ops = vec![];
+            for i in (round % 4..40).step_by(3) {
+                match i % 5 == 0 && round % 2 == 1 {
+                    true => ops.push(Operation::Delete(vec![i])),
+                    false => ops.push(Operation::Set(vec![i], vec![round])),
+                }
+            }
+            let ops = resolve(&mem, ops).expect("fail to resolve");
+            for op in ops.iter() {
+                if let Operation::Delete(k) = op {
+                    stored.remove(k);
+                }
+            }
+            mem.apply(ops);
+            mem.take_dirty(&mut |k, node| {
+                stored.insert(k.to_vec(), node);
+            });
+            assert_eq!(stored.len(), mem.len());
+
+            let loaded = MemoryTreeDB::load(mem.root_key(), &mut |k| Ok(stored.get(k).cloned()),
+                                            ErrorEnumsStruct::PREFIXED_TREE_INVALID).expect("fail to load");
+            assert_eq!(loaded.root_hash(), mem.root_hash());
+            assert_eq!(loaded.len(), mem.len());
+        }
+
+        let root = mem.root_key().expect("tree is not empty").to_vec();
+        let node = stored.get_mut(&root).expect("root is stored");
+        *node.last_mut().expect("node is not empty") ^= 1;
+        let loaded = MemoryTreeDB::load(Some(root.as_slice()), &mut |k| Ok(stored.get(k).cloned()),
+                                        ErrorEnumsStruct::PREFIXED_TREE_INVALID).expect("fail to load");
+        assert_ne!(loaded.root_hash(), mem.root_hash());
+        stored.remove(&root);
+        assert!(MemoryTreeDB::load(Some(root.as_slice()), &mut |k| Ok(stored.get(k).cloned()),
+                                   ErrorEnumsStruct::PREFIXED_TREE_INVALID).is_err());
+    }
+
+    #[test]
+    pub fn test_prove_verify() {
+        let mut mem = MemoryTreeDB::new();
+        for i in 0..10u8 {
+            mem.set(&[i * 2], vec![i]).expect("fail to set");
+        }
+        let mut req = ProveRequest::default();
+        req.insert(vec![4]);
+        req.insert(vec![7]);
+        let root = mem.root_hash();
+        let resp = mem.prove(req).expect("fail to prove");
+
+        let mut v_req = VerifyRequest::new(resp.proof.clone(), root);
+        v_req.insert(vec![4], vec![2]);
+        assert!(mem.verify(v_req).expect("fail to verify").valid);
+
+        let mut v_req = VerifyRequest::new(resp.proof, root);
+        v_req.insert(vec![4], vec![3]);
+        assert!(!mem.verify(v_req).expect("fail to verify").valid);
+    }
+
+    #[test]
+    pub fn test_delete() {
+        let mut mem = MemoryTreeDB::new();
+        let empty = mem.root_hash();
+        mem.set(&[1], vec![1]).unwrap();
+        assert_ne!(mem.root_hash(), empty);
+        assert_eq!(mem.get(&[1]).unwrap().as_deref(), Some(&[1u8][..]));
+        assert_eq!(mem.delete(&[1]).unwrap(), Some(vec![1]));
+        assert_eq!(mem.root_hash(), empty);
+        assert_eq!(mem.get(&[1]).unwrap(), None);
+    }
+}
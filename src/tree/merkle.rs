@@ -1,10 +1,15 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::path::Path;
 use merk::{BatchEntry, Hash, Merk, Op};
 use merk::proofs::Query;
+use merk::tree::{kv_hash, Tree, NULL_HASH};
 use crate::error::{ErrorEnums, ErrorEnumsStruct, ZKError, ZKResult};
 use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
-use crate::tree::operation::Operation;
-use crate::tree::tree::{DB, TreeDB};
+use crate::tree::multiproof::CompactProof;
+use crate::tree::operation::{resolve, Operation};
+use crate::tree::tree::{Visitor, DB, TreeDB};
 
 pub struct MerkleRocksDB {
     m: Merk,
@@ -18,47 +23,246 @@ impl MerkleRocksDB {
     pub fn new(m: Merk) -> Self {
         Self { m }
     }
-    pub fn new_with_path<P: AsRef<Path>>(p: P) -> Self {
-        let merk = Merk::open(p).expect("fail to create ");
-        Self { m: merk }
+    pub(crate) fn merk(&self) -> &Merk {
+        &self.m
+    }
+
+    pub fn new_with_path<P: AsRef<Path>>(p: P) -> ZKResult<Self> {
+        Merk::open(p).map(|m| Self { m }).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::MERKLE_OPEN_FAILED).with_error(Box::new(e))
+        })
+    }
+
+    /// opens the store and refuses it when `check_integrity` finds any corrupt node
+    pub fn open_verified<P: AsRef<Path>>(p: P) -> ZKResult<Self> {
+        let db = Self::new_with_path(p)?;
+        let report = db.check_integrity()?;
+        if !report.is_valid() {
+            let keys: Vec<String> = report.corrupt.iter().map(|n| hex::encode(&n.key)).collect();
+            return Err(ZKError::new(ErrorEnumsStruct::MERKLE_CORRUPTED_NODE.get_code(),
+                                    format!("{} corrupt nodes,keys=[{}]", keys.len(), keys.join(","))));
+        }
+        Ok(db)
+    }
+
+    /// walks every node stored on disk, recomputes its kv hash and the hashes referenced by its
+    /// parent, and compares the recomputed root with the stored root hash
+    pub fn check_integrity(&self) -> ZKResult<IntegrityReport> {
+        let mut report = IntegrityReport::default();
+        let mut hashes: HashMap<Vec<u8>, Hash> = HashMap::new();
+        let mut links: Vec<(Vec<u8>, Vec<u8>, Hash)> = Vec::new();
+
+        self.for_each_node(|key, node| {
+            report.nodes += 1;
+            let tree = match node {
+                Some(tree) => tree,
+                None => {
+                    report.corrupt.push(CorruptNode { key: key.to_vec(), kind: CorruptKind::Undecodable });
+                    return;
+                }
+            };
+            if kv_hash(tree.key(), tree.value()) != *tree.kv_hash() {
+                report.corrupt.push(CorruptNode { key: key.to_vec(), kind: CorruptKind::KvHashMismatch });
+            }
+            for left in [true, false] {
+                if let Some(link) = tree.link(left) {
+                    links.push((key.to_vec(), link.key().to_vec(), *link.hash()));
+                }
+            }
+            hashes.insert(key.to_vec(), tree.hash());
+        });
+
+        let mut children = HashSet::new();
+        for (parent, child, stored) in links {
+            match hashes.get(&child) {
+                None => report.corrupt.push(CorruptNode { key: parent, kind: CorruptKind::MissingChild(child.clone()) }),
+                Some(h) if *h != stored => report.corrupt.push(CorruptNode { key: child.clone(), kind: CorruptKind::ChildHashMismatch }),
+                _ => {}
+            }
+            children.insert(child);
+        }
+
+        let expected = self.m.root_hash();
+        let roots: Vec<&Vec<u8>> = hashes.keys().filter(|k| !children.contains(*k)).collect();
+        let root_valid = match roots.as_slice() {
+            [] => expected == NULL_HASH,
+            [root] => hashes[*root] == expected,
+            _ => false,
+        };
+        if !root_valid {
+            report.corrupt.push(CorruptNode { key: vec![], kind: CorruptKind::RootHashMismatch });
+        }
+        Ok(report)
+    }
+
+    /// every key/value pair stored in the tree, in key order
+    pub(crate) fn for_each_entry<F: FnMut(&[u8], &[u8])>(&self, mut f: F) -> ZKResult<()> {
+        let mut undecodable = None;
+        self.for_each_node(|key, node| {
+            match node {
+                Some(tree) => f(tree.key(), tree.value()),
+                None => {
+                    undecodable.get_or_insert_with(|| key.to_vec());
+                }
+            }
+        });
+        match undecodable {
+            Some(key) => Err(ZKError::new(ErrorEnumsStruct::MERKLE_CORRUPTED_NODE.get_code(),
+                                          format!("undecodable node,key={}", hex::encode(key)))),
+            None => Ok(()),
+        }
+    }
+
+    /// every node stored in rocksdb, `None` when the bytes can not be decoded
+    fn for_each_node<F: FnMut(&[u8], Option<Tree>)>(&self, mut f: F) {
+        let mut iter = self.m.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            if let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+                let node = catch_unwind(AssertUnwindSafe(|| Tree::decode(key.to_vec(), value))).ok();
+                f(key, node);
+            }
+            iter.next();
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CorruptKind {
+    Undecodable,
+    KvHashMismatch,
+    ChildHashMismatch,
+    MissingChild(Vec<u8>),
+    RootHashMismatch,
+}
+
+#[derive(Debug)]
+pub struct CorruptNode {
+    pub key: Vec<u8>,
+    pub kind: CorruptKind,
+}
+
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub nodes: usize,
+    pub corrupt: Vec<CorruptNode>,
+}
+
+impl IntegrityReport {
+    pub fn is_valid(&self) -> bool {
+        self.corrupt.is_empty()
     }
 }
 
 impl DB for MerkleRocksDB {
-    fn get(&self, k: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
-        self.m.get(k.as_slice()).map_err(|e| {
+    /// merk copies the value out of rocksdb, so reads are always owned
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        self.m.get(k).map(|v| v.map(Cow::Owned)).map_err(|e| {
             ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
         })
     }
 
-    fn set(&mut self, k: Vec<u8>, v: Vec<u8>) -> ZKResult<Vec<u8>> {
-        self.m.apply(&vec![(k.clone(), Op::Put(v))], &[]).map_err(|e| {
+    /// merk does not report what a batch replaced, so the old value is read first
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        let old = self.get(k)?.map(Cow::into_owned);
+        self.m.apply(&[(k.to_vec(), Op::Put(v))], &[]).map_err(|e| {
             ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
-        }).map(|_| {
-            k.clone()
-        })
+        })?;
+        Ok(old)
     }
 
 
-    fn delete(&mut self, k: Vec<u8>) -> ZKResult<()> {
-        self.m.apply(&vec![(k, Op::Delete)], &[]).map_err(|e| {
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        let old = self.get(k)?.map(Cow::into_owned);
+        if old.is_none() {
+            return Ok(None);
+        }
+        self.m.apply(&[(k.to_vec(), Op::Delete)], &[]).map_err(|e| {
             ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
-        })
+        })?;
+        Ok(old)
+    }
+
+    /// one rocksdb iterator visits the keys in sorted order, seeking forward from one to the next,
+    /// instead of a full lookup per key. merk keeps its rocksdb handle to itself, its raw iterator
+    /// is the batched read it hands out
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|a, b| keys[*a].cmp(keys[*b]));
+        let mut ret = vec![None; keys.len()];
+        let mut iter = self.m.raw_iter();
+        for i in order {
+            iter.seek(keys[i]);
+            if !iter.valid() || iter.key() != Some(keys[i]) {
+                continue;
+            }
+            let value = iter.value().unwrap_or_default();
+            let node = catch_unwind(AssertUnwindSafe(|| Tree::decode(keys[i].to_vec(), value))).map_err(|_| {
+                ZKError::new(ErrorEnumsStruct::MERKLE_CORRUPTED_NODE.get_code(),
+                             format!("undecodable node,key={}", hex::encode(keys[i])))
+            })?;
+            ret[i] = Some(node.value().to_vec());
+        }
+        Ok(ret)
+    }
+
+    /// merk offers no range iterator over its keys, so the whole store is scanned
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut ret = Vec::new();
+        self.scan(start, end, &mut |k, v| {
+            ret.push((k.to_vec(), v.to_vec()));
+            Ok(())
+        })?;
+        Ok(ret)
+    }
+
+    fn scan(&self, start: &[u8], end: &[u8], f: &mut Visitor) -> ZKResult<()> {
+        let mut ret = Ok(());
+        self.for_each_entry(|k, v| {
+            if ret.is_ok() && k >= start && (end.is_empty() || k < end) {
+                ret = f(k, v);
+            }
+        })?;
+        ret
     }
 }
 
-fn to_batch(ops: Vec<Operation>) -> Vec<BatchEntry> {
+/// merk wants a sorted batch holding each key once, resolving the operations gives exactly that
+fn to_batch(db: &MerkleRocksDB, ops: Vec<Operation>) -> ZKResult<Vec<BatchEntry>> {
     let mut batch = Vec::new();
-    for val in ops {
+    for val in resolve(db, ops)? {
         match val {
             Operation::Set(k, v) => batch.push((k, Op::Put(v))),
             Operation::Delete(k) => batch.push((k, Op::Delete)),
+            _ => unreachable!("resolve only emits sets and deletes"),
         }
     }
-    batch
+    Ok(batch)
 }
 
 
+/// checks a merk proof against the expected root, shared by every backend emitting merk proofs
+pub(crate) fn verify_merk_proof(req: VerifyRequest) -> ZKResult<VerifyResponse> {
+    let res = merk::verify(req.proof.as_slice(), req.expected_root as Hash).map_err(|e| {
+        ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
+    })?;
+    // TODO,需要递归tree往上构造
+    let mut ret = VerifyResponse::default();
+    for (k, v) in req.kv {
+        let value_opt = res.get(k.as_slice()).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
+        })?;
+        if let Some(value) = value_opt {
+            if value != v {
+                ret.valid = false;
+                return Ok(ret);
+            }
+        }
+    }
+    ret.valid = true;
+    Ok(ret)
+}
+
 impl TreeDB for MerkleRocksDB {
     fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
         let mut q = Query::default();
@@ -73,24 +277,7 @@ impl TreeDB for MerkleRocksDB {
     }
 
     fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
-        let res = merk::verify(req.proof.as_slice(), req.expected_root as Hash).map_err(|e| {
-            ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
-        })?;
-        // TODO,需要递归tree往上构造
-        let mut ret = VerifyResponse::default();
-        for (k, v) in req.kv {
-            let value_opt = res.get(k.as_slice()).map_err(|e| {
-                ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
-            })?;
-            if let Some(value) = value_opt {
-                if value != v {
-                    ret.valid = false;
-                    return Ok(ret);
-                }
-            }
-        }
-        ret.valid = true;
-        Ok(ret)
+        verify_merk_proof(req)
     }
 
     fn commit(&mut self, mut operations: Vec<Operation>) -> ZKResult<()> {
@@ -107,9 +294,41 @@ impl TreeDB for MerkleRocksDB {
 }
 
 impl MerkleRocksDB {
+    /// one merk proof per request, packed into a single multiproof sharing their hashes
+    pub fn prove_compact(&self, queries: Vec<ProveRequest>) -> ZKResult<CompactProof> {
+        let mut proofs = Vec::with_capacity(queries.len());
+        for q in queries {
+            proofs.push(self.prove(q)?.proof);
+        }
+        CompactProof::from_proofs(proofs.as_slice())
+    }
+
+    pub(crate) fn get_aux(&self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        self.m.get_aux(k).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
+        })
+    }
+
+    /// commits the operations and writes `aux` (`None` deletes) in the same rocksdb batch,
+    /// aux entries stay outside of the tree and its root
+    pub(crate) fn commit_with_aux(&mut self, operations: Vec<Operation>, aux: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> ZKResult<()> {
+        let aux: Vec<BatchEntry> = aux.into_iter().map(|(k, v)| match v {
+            Some(v) => (k, Op::Put(v)),
+            None => (k, Op::Delete),
+        }).collect();
+        self.apply_batch(operations, aux.as_slice())?;
+        self.m.flush().map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
+        })
+    }
+
     fn batch_operation(&mut self, ops: Vec<Operation>) -> ZKResult<()> {
-        let batches = to_batch(ops);
-        self.m.apply(&batches, &[]).map_err(|e| {
+        self.apply_batch(ops, &[])
+    }
+
+    fn apply_batch(&mut self, ops: Vec<Operation>, aux: &[BatchEntry]) -> ZKResult<()> {
+        let batches = to_batch(self, ops)?;
+        self.m.apply(&batches, aux).map_err(|e| {
             ZKError::from(ErrorEnumsStruct::UNKNOWN).with_error(Box::new(e))
         })
     }
@@ -129,4 +348,92 @@ pub fn test_merke() {
 
     let res = merk.get(&[1, 2, 3]).unwrap();
     println!("{:?}", String::from_utf8(res.unwrap()));
-}
\ No newline at end of file
+}
+
+#[test]
+pub fn test_check_integrity() {
+    let path = std::env::temp_dir().join(format!("zkp_merk_integrity_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = MerkleRocksDB::new_with_path(&path).expect("fail to open");
+    db.commit(vec![
+        Operation::Set(vec![1, 2, 3], vec![4, 5, 6]),
+        Operation::Set(vec![7, 8, 9], vec![1, 1, 1]),
+    ]).expect("fail to commit");
+    let report = db.check_integrity().expect("fail to check");
+    assert!(report.is_valid());
+    assert!(report.nodes >= 2);
+    drop(db);
+    std::fs::remove_dir_all(&path).expect("fail to remove");
+}
+
+#[test]
+pub fn test_detects_corrupt_node() {
+    let path = std::env::temp_dir().join(format!("zkp_merk_corrupt_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = MerkleRocksDB::new_with_path(&path).expect("fail to open");
+    db.commit(vec![
+        Operation::Set(vec![1, 2, 3], vec![4, 5, 6]),
+        Operation::Set(vec![7, 8, 9], vec![1, 1, 1]),
+        Operation::Set(vec![9], vec![9]),
+    ]).expect("fail to commit");
+    drop(db);
+
+    // merk stores its nodes in the default column family, the value is the last field of a node
+    {
+        use merk::rocksdb::{Options, DB as RawDB};
+        let raw = RawDB::open_cf(&Options::default(), &path, ["aux", "internal"]).expect("fail to open rocksdb");
+        let mut node = raw.get([7u8, 8, 9]).expect("fail to read").expect("node is stored");
+        let last = node.len() - 1;
+        node[last] ^= 1;
+        raw.put([7u8, 8, 9], node).expect("fail to write");
+    }
+
+    let db = MerkleRocksDB::new_with_path(&path).expect("fail to open");
+    let report = db.check_integrity().expect("fail to check");
+    assert!(!report.is_valid());
+    assert!(report.corrupt.iter().any(|n| n.key == vec![7, 8, 9] && n.kind == CorruptKind::KvHashMismatch));
+    drop(db);
+    let err = MerkleRocksDB::open_verified(&path).err().expect("corrupt store is refused");
+    assert_eq!(err.get_code(), ErrorEnumsStruct::MERKLE_CORRUPTED_NODE.get_code());
+    std::fs::remove_dir_all(&path).expect("fail to remove");
+}
+
+#[test]
+pub fn test_deletes_of_missing_keys() {
+    let path = std::env::temp_dir().join(format!("zkp_merk_deletes_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = MerkleRocksDB::new_with_path(&path).expect("fail to open");
+    db.commit(vec![
+        Operation::Set(b"ord/1".to_vec(), vec![1]),
+        Operation::Set(b"ord/2".to_vec(), vec![2]),
+    ]).expect("fail to commit");
+
+    db.commit(vec![
+        Operation::Set(b"ord/25".to_vec(), vec![0]),
+        Operation::DeleteRange(b"ord/2".to_vec(), b"ord/3".to_vec()),
+        Operation::Delete(b"missing".to_vec()),
+    ]).expect("fail to commit");
+    assert_eq!(db.get(b"ord/1").expect("fail to get").map(|v| v.into_owned()), Some(vec![1]));
+    assert!(db.get(b"ord/2").expect("fail to get").is_none());
+    assert!(db.get(b"ord/25").expect("fail to get").is_none());
+    drop(db);
+    std::fs::remove_dir_all(&path).expect("fail to remove");
+}
+
+#[test]
+pub fn test_get_many() {
+    let path = std::env::temp_dir().join(format!("zkp_merk_get_many_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = MerkleRocksDB::new_with_path(&path).expect("fail to open");
+    db.commit((0..20u8).map(|i| Operation::Set(vec![i * 2], vec![i])).collect()).expect("fail to commit");
+
+    let keys: [&[u8]; 6] = [&[30], &[1], &[0], &[30], &[99], &[38]];
+    let got = db.get_many(&keys).expect("fail to get");
+    assert_eq!(got, vec![Some(vec![15]), None, Some(vec![0]), Some(vec![15]), None, Some(vec![19])]);
+    for (k, v) in keys.iter().zip(got) {
+        assert_eq!(db.get(k).expect("fail to get").map(Cow::into_owned), v);
+    }
+    assert!(db.get_many(&[]).expect("fail to get").is_empty());
+    drop(db);
+    std::fs::remove_dir_all(&path).expect("fail to remove");
+}
@@ -1,5 +1,16 @@
 pub mod tree;
 pub mod couple;
 pub mod merkle;
+pub mod memory;
 pub mod operation;
-pub mod smt;
\ No newline at end of file
+pub mod smt;
+pub mod hasher;
+pub mod poseidon;
+pub mod wal;
+pub mod prefixed;
+pub mod codec;
+pub mod multiproof;
+pub mod bundle;
+pub mod solidity;
+pub mod sync;
+pub mod dump;
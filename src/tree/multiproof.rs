@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use merk::Hash;
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::tree::codec::{decode_with, header, Codec, Reader, TAG_COMPACT_PROOF};
+use crate::tree::couple::{VerifyRequest, VerifyResponse};
+
+/// op tags of the merk proof encoding
+const MERK_PUSH_HASH: u8 = 0x01;
+const MERK_PUSH_KV_HASH: u8 = 0x02;
+const MERK_PUSH_KV: u8 = 0x03;
+const MERK_PARENT: u8 = 0x10;
+const MERK_CHILD: u8 = 0x11;
+
+const FLAG_PLAIN: u8 = 0;
+const FLAG_DEFLATE: u8 = 1;
+
+/// compressed payloads inflating past this are rejected
+#[cfg(feature = "compression")]
+const MAX_INFLATED: usize = 64 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+enum ProofOp {
+    /// index into the hash table of the multiproof
+    Hash(u32),
+    KvHash(u32),
+    Kv(Vec<u8>, Vec<u8>),
+    Parent,
+    Child,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProofStats {
+    pub proofs: usize,
+    /// bytes of the merk proofs the multiproof was built from
+    pub raw_bytes: usize,
+    /// bytes of the encoded multiproof, uncompressed
+    pub compact_bytes: usize,
+    pub hashes: usize,
+    pub unique_hashes: usize,
+}
+
+/// several merk proofs against the same root, every hash they carry is stored once in a shared
+/// table and referenced by index. the proofs keep their own structure, `expand` gives the
+/// original merk bytes back
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactProof {
+    hashes: Vec<Hash>,
+    proofs: Vec<Vec<ProofOp>>,
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u32) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn varint(r: &mut Reader<'_>) -> ZKResult<u32> {
+    let mut ret = 0u32;
+    for shift in (0..35).step_by(7) {
+        let b = r.u8()?;
+        ret |= ((b & 0x7f) as u32).checked_shl(shift).ok_or_else(|| r.error())?;
+        if b & 0x80 == 0 {
+            return Ok(ret);
+        }
+    }
+    Err(r.error())
+}
+
+impl CompactProof {
+    pub fn from_proofs(proofs: &[Vec<u8>]) -> ZKResult<Self> {
+        let mut ret = CompactProof::default();
+        let mut index: HashMap<Hash, u32> = HashMap::new();
+        for proof in proofs {
+            let mut intern = |h: &[u8]| -> u32 {
+                let mut hash = [0u8; 32];
+                hash.copy_from_slice(h);
+                *index.entry(hash).or_insert_with(|| {
+                    ret.hashes.push(hash);
+                    ret.hashes.len() as u32 - 1
+                })
+            };
+            let mut ops = Vec::new();
+            let mut r = Reader::new(proof.as_slice(), ErrorEnumsStruct::MERK_PROOF_INVALID);
+            while !r.is_empty() {
+                let op = match r.u8()? {
+                    MERK_PUSH_HASH => ProofOp::Hash(intern(r.take(32)?)),
+                    MERK_PUSH_KV_HASH => ProofOp::KvHash(intern(r.take(32)?)),
+                    MERK_PUSH_KV => {
+                        let key_len = r.u8()? as usize;
+                        let key = r.take(key_len)?.to_vec();
+                        let len = r.take(2)?;
+                        let value_len = u16::from_be_bytes([len[0], len[1]]) as usize;
+                        ProofOp::Kv(key, r.take(value_len)?.to_vec())
+                    }
+                    MERK_PARENT => ProofOp::Parent,
+                    MERK_CHILD => ProofOp::Child,
+                    tag => return Err(ZKError::new(ErrorEnumsStruct::MERK_PROOF_INVALID.get_code(), format!("unknown op {:#x}", tag))),
+                };
+                ops.push(op);
+            }
+            ret.proofs.push(ops);
+        }
+        Ok(ret)
+    }
+
+    /// the merk proofs in the order they were added
+    pub fn expand(&self) -> ZKResult<Vec<Vec<u8>>> {
+        let hash = |i: &u32| self.hashes.get(*i as usize).ok_or_else(|| ZKError::from(ErrorEnumsStruct::CODEC_INVALID));
+        let mut ret = Vec::with_capacity(self.proofs.len());
+        for ops in self.proofs.iter() {
+            let mut buf = Vec::new();
+            for op in ops {
+                match op {
+                    ProofOp::Hash(i) => {
+                        buf.push(MERK_PUSH_HASH);
+                        buf.extend_from_slice(hash(i)?);
+                    }
+                    ProofOp::KvHash(i) => {
+                        buf.push(MERK_PUSH_KV_HASH);
+                        buf.extend_from_slice(hash(i)?);
+                    }
+                    ProofOp::Kv(k, v) => {
+                        buf.push(MERK_PUSH_KV);
+                        buf.push(k.len() as u8);
+                        buf.extend_from_slice(k);
+                        buf.extend_from_slice(&(v.len() as u16).to_be_bytes());
+                        buf.extend_from_slice(v);
+                    }
+                    ProofOp::Parent => buf.push(MERK_PARENT),
+                    ProofOp::Child => buf.push(MERK_CHILD),
+                }
+            }
+            ret.push(buf);
+        }
+        Ok(ret)
+    }
+
+    pub fn stats(&self) -> ProofStats {
+        let mut stats = ProofStats {
+            proofs: self.proofs.len(),
+            compact_bytes: self.encode().len(),
+            unique_hashes: self.hashes.len(),
+            ..Default::default()
+        };
+        for op in self.proofs.iter().flatten() {
+            stats.raw_bytes += match op {
+                ProofOp::Hash(_) | ProofOp::KvHash(_) => {
+                    stats.hashes += 1;
+                    33
+                }
+                ProofOp::Kv(k, v) => 4 + k.len() + v.len(),
+                ProofOp::Parent | ProofOp::Child => 1,
+            };
+        }
+        stats
+    }
+
+    /// every pair has to be carried by one of the proofs, an empty expected value proves the key
+    /// is absent
+    pub fn verify(&self, expected_root: [u8; 32], kv: &HashMap<Vec<u8>, Vec<u8>>) -> ZKResult<VerifyResponse> {
+        let mut maps = Vec::with_capacity(self.proofs.len());
+        for proof in self.expand()? {
+            maps.push(merk::verify(proof.as_slice(), expected_root as Hash).map_err(|e| {
+                ZKError::from(ErrorEnumsStruct::MERK_PROOF_INVALID).with_error(Box::new(e))
+            })?);
+        }
+        let mut ret = VerifyResponse::default();
+        for (k, v) in kv.iter() {
+            // a proof not covering the key fails the lookup, the first one covering it decides
+            let found = maps.iter().find_map(|m| m.get(k.as_slice()).ok());
+            let matches = match found {
+                Some(Some(value)) => value == v.as_slice(),
+                Some(None) => v.is_empty(),
+                None => false,
+            };
+            if !matches {
+                return Ok(ret);
+            }
+        }
+        ret.valid = true;
+        Ok(ret)
+    }
+
+    fn encode_payload(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_varint(&mut buf, self.hashes.len() as u32);
+        for h in self.hashes.iter() {
+            buf.extend_from_slice(h);
+        }
+        put_varint(&mut buf, self.proofs.len() as u32);
+        for ops in self.proofs.iter() {
+            put_varint(&mut buf, ops.len() as u32);
+            for op in ops {
+                match op {
+                    ProofOp::Hash(i) => {
+                        buf.push(MERK_PUSH_HASH);
+                        put_varint(&mut buf, *i);
+                    }
+                    ProofOp::KvHash(i) => {
+                        buf.push(MERK_PUSH_KV_HASH);
+                        put_varint(&mut buf, *i);
+                    }
+                    ProofOp::Kv(k, v) => {
+                        buf.push(MERK_PUSH_KV);
+                        put_varint(&mut buf, k.len() as u32);
+                        buf.extend_from_slice(k);
+                        put_varint(&mut buf, v.len() as u32);
+                        buf.extend_from_slice(v);
+                    }
+                    ProofOp::Parent => buf.push(MERK_PARENT),
+                    ProofOp::Child => buf.push(MERK_CHILD),
+                }
+            }
+        }
+        buf
+    }
+
+    fn decode_payload(payload: &[u8]) -> ZKResult<Self> {
+        let mut r = Reader::new(payload, ErrorEnumsStruct::CODEC_INVALID);
+        let mut ret = CompactProof::default();
+        for _ in 0..varint(&mut r)? {
+            let mut h = [0u8; 32];
+            h.copy_from_slice(r.take(32)?);
+            ret.hashes.push(h);
+        }
+        let index = |r: &mut Reader<'_>, hashes: usize| -> ZKResult<u32> {
+            let i = varint(r)?;
+            if i as usize >= hashes {
+                return Err(r.error());
+            }
+            Ok(i)
+        };
+        for _ in 0..varint(&mut r)? {
+            let mut ops = Vec::new();
+            for _ in 0..varint(&mut r)? {
+                let op = match r.u8()? {
+                    MERK_PUSH_HASH => ProofOp::Hash(index(&mut r, ret.hashes.len())?),
+                    MERK_PUSH_KV_HASH => ProofOp::KvHash(index(&mut r, ret.hashes.len())?),
+                    // merk encodes lengths in one and two bytes, `expand` could not write longer ones back
+                    MERK_PUSH_KV => {
+                        let key_len = varint(&mut r)? as usize;
+                        if key_len > u8::MAX as usize {
+                            return Err(r.error());
+                        }
+                        let key = r.take(key_len)?.to_vec();
+                        let value_len = varint(&mut r)? as usize;
+                        if value_len > u16::MAX as usize {
+                            return Err(r.error());
+                        }
+                        ProofOp::Kv(key, r.take(value_len)?.to_vec())
+                    }
+                    MERK_PARENT => ProofOp::Parent,
+                    MERK_CHILD => ProofOp::Child,
+                    _ => return Err(r.error()),
+                };
+                ops.push(op);
+            }
+            ret.proofs.push(ops);
+        }
+        r.finish()?;
+        Ok(ret)
+    }
+
+    /// same message as `encode` with the payload deflated
+    #[cfg(feature = "compression")]
+    pub fn encode_compressed(&self) -> Vec<u8> {
+        let mut buf = header(TAG_COMPACT_PROOF);
+        buf.push(FLAG_DEFLATE);
+        buf.extend_from_slice(miniz_oxide::deflate::compress_to_vec(self.encode_payload().as_slice(), 9).as_slice());
+        buf
+    }
+}
+
+/// verifier for requests whose `proof` is an encoded `CompactProof`, plain or compressed
+pub fn verify_compact(req: VerifyRequest) -> ZKResult<VerifyResponse> {
+    CompactProof::decode(req.proof.as_slice())?.verify(req.expected_root, &req.kv)
+}
+
+#[cfg(feature = "compression")]
+fn inflate(data: &[u8]) -> ZKResult<Vec<u8>> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_INFLATED).map_err(|e| {
+        ZKError::new(ErrorEnumsStruct::CODEC_INVALID.get_code(), format!("inflate failed: {}", e))
+    })
+}
+
+#[cfg(not(feature = "compression"))]
+fn inflate(_data: &[u8]) -> ZKResult<Vec<u8>> {
+    Err(ZKError::new(ErrorEnumsStruct::CODEC_INVALID.get_code(), "built without the compression feature".to_string()))
+}
+
+/// `flag | payload`, the payload is deflated when the flag says so
+impl Codec for CompactProof {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = header(TAG_COMPACT_PROOF);
+        buf.push(FLAG_PLAIN);
+        buf.extend_from_slice(self.encode_payload().as_slice());
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> ZKResult<Self> {
+        let (flag, payload) = decode_with(bytes, TAG_COMPACT_PROOF, |r| {
+            let flag = r.u8()?;
+            Ok((flag, r.rest()))
+        })?;
+        match flag {
+            FLAG_PLAIN => Self::decode_payload(payload),
+            FLAG_DEFLATE => Self::decode_payload(inflate(payload)?.as_slice()),
+            _ => Err(ZKError::new(ErrorEnumsStruct::CODEC_INVALID.get_code(), format!("unknown proof flag {}", flag))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use crate::error::ErrorEnumsStruct;
+    use crate::tree::codec::Codec;
+    use crate::tree::couple::ProveRequest;
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::multiproof::{CompactProof, ProofOp};
+    use crate::tree::operation::Operation;
+    use crate::tree::tree::TreeDB;
+
+    /// hand written merk proofs sharing the hashes of the upper tree
+    fn proofs() -> Vec<Vec<u8>> {
+        let kv = |k: &[u8], v: &[u8]| [&[0x03, k.len() as u8][..], k, &(v.len() as u16).to_be_bytes(), v].concat();
+        let hash = |tag: u8, b: u8| [&[tag][..], &[b; 32]].concat();
+        vec![
+            [kv(b"a", b"1"), hash(0x01, 7), vec![0x10], hash(0x02, 8), vec![0x11], hash(0x01, 9), vec![0x10]].concat(),
+            [hash(0x01, 6), kv(b"c", b"3"), vec![0x11], hash(0x02, 8), vec![0x10], hash(0x01, 9), vec![0x11]].concat(),
+        ]
+    }
+
+    #[test]
+    pub fn test_compact_proof() {
+        let raw = proofs();
+        let compact = CompactProof::from_proofs(raw.as_slice()).expect("fail to compact");
+        assert_eq!(compact.expand().expect("fail to expand"), raw);
+
+        let stats = compact.stats();
+        assert_eq!(stats.proofs, 2);
+        assert_eq!(stats.raw_bytes, raw.iter().map(|p| p.len()).sum::<usize>());
+        assert_eq!((stats.hashes, stats.unique_hashes), (6, 4));
+        assert!(stats.compact_bytes < stats.raw_bytes);
+
+        let bytes = compact.encode();
+        assert_eq!(CompactProof::decode(&bytes).expect("fail to decode"), compact);
+        let err = CompactProof::decode(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::CODEC_INVALID.get_code());
+
+        let err = CompactProof::from_proofs(&[vec![0x01, 0]]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::MERK_PROOF_INVALID.get_code());
+    }
+
+    #[test]
+    pub fn test_compact_tree_proofs() {
+        let mut mem = MemoryTreeDB::new();
+        mem.commit((0..32u8).map(|i| Operation::Set(vec![i], vec![i, i])).collect()).expect("fail to commit");
+        let root = mem.root_hash();
+        let mut raw = Vec::new();
+        let mut kv = HashMap::new();
+        for k in [3u8, 17, 30] {
+            let mut req = ProveRequest::default();
+            req.insert(vec![k]);
+            raw.push(mem.prove(req).expect("fail to prove").proof);
+            kv.insert(vec![k], vec![k, k]);
+        }
+        let compact = CompactProof::from_proofs(raw.as_slice()).expect("fail to compact");
+        assert!(compact.stats().unique_hashes < compact.stats().hashes);
+        let decoded = CompactProof::decode(&compact.encode()).expect("fail to decode");
+        assert!(decoded.verify(root, &kv).expect("fail to verify").valid);
+
+        let mut wrong = kv.clone();
+        wrong.insert(vec![17], vec![0]);
+        assert!(!decoded.verify(root, &wrong).expect("fail to verify").valid);
+
+        let mut tampered = decoded.clone();
+        tampered.hashes[0][0] ^= 1;
+        assert!(!tampered.verify(root, &kv).map(|r| r.valid).unwrap_or(false));
+    }
+
+    #[test]
+    pub fn test_rejects_oversized_kv() {
+        for (key, value) in [(vec![1u8; 256], vec![1u8]), (vec![1u8], vec![1u8; 1 << 16])] {
+            let proof = CompactProof { hashes: vec![], proofs: vec![vec![ProofOp::Kv(key, value)]] };
+            let err = CompactProof::decode(&proof.encode()).unwrap_err();
+            assert_eq!(err.get_code(), ErrorEnumsStruct::CODEC_INVALID.get_code());
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    pub fn test_compressed_proof() {
+        let compact = CompactProof::from_proofs(proofs().as_slice()).expect("fail to compact");
+        let bytes = compact.encode_compressed();
+        assert_eq!(CompactProof::decode(&bytes).expect("fail to decode"), compact);
+    }
+}
@@ -1,4 +1,227 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::tree::tree::{range_bounds, DB};
+
+/// with the `serde` feature an operation is written as `{"set": ["<hex key>", "<hex value>"]}`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
 pub enum Operation {
-    Set(Vec<u8>, Vec<u8>),
-    Delete(Vec<u8>),
+    Set(#[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))] Vec<u8>, #[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))] Vec<u8>),
+    Delete(#[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))] Vec<u8>),
+    /// adds to a counter stored as a 16 byte big-endian `i128`, a missing key counts as zero
+    Increment(#[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))] Vec<u8>, i128),
+    /// combines the operand with the current value through the merge operator of the stack, see `MergeMiddleware`
+    Merge(#[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))] Vec<u8>, #[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))] Vec<u8>),
+    /// deletes every key in `[start, end)`, an empty `end` deletes up to the last key
+    DeleteRange(#[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))] Vec<u8>, #[cfg_attr(feature = "serde", serde(with = "crate::tree::codec::hex_serde::bytes"))] Vec<u8>),
+}
+
+impl Operation {
+    /// the key written, or the start of the range
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Operation::Set(k, _) | Operation::Delete(k) | Operation::Increment(k, _) | Operation::Merge(k, _) => k.as_slice(),
+            Operation::DeleteRange(start, _) => start.as_slice(),
+        }
+    }
+}
+
+/// `key` must hold `expected` for a conditional commit to go through, `None` meaning absent
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Precondition {
+    pub key: Vec<u8>,
+    pub expected: Option<Vec<u8>>,
+}
+
+impl Precondition {
+    pub fn new(key: Vec<u8>, expected: Option<Vec<u8>>) -> Self {
+        Self { key, expected }
+    }
+}
+
+pub fn encode_counter(v: i128) -> Vec<u8> {
+    v.to_be_bytes().to_vec()
+}
+
+pub fn decode_counter(k: &[u8], v: &[u8]) -> ZKResult<i128> {
+    let bytes: [u8; 16] = v.try_into().map_err(|_| {
+        ZKError::new(ErrorEnumsStruct::OPERATION_INVALID.get_code(),
+                     format!("key {} holds {} bytes,not a counter", hex::encode(k), v.len()))
+    })?;
+    Ok(i128::from_be_bytes(bytes))
+}
+
+/// `(key, current value, operand) -> new value`
+pub type MergeFn = Arc<dyn Fn(&[u8], Option<&[u8]>, &[u8]) -> ZKResult<Vec<u8>> + Send + Sync>;
+
+type MergeEntry = (Vec<u8>, MergeFn);
+
+/// merge operators by key prefix, the longest matching prefix wins.
+/// each stack carries its own through `MergeMiddleware`, so two trees may use the same prefix
+#[derive(Clone, Default)]
+pub struct MergeOperators {
+    operators: Vec<MergeEntry>,
+}
+
+impl MergeOperators {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// registers `f` for every key starting with `prefix`, replacing an operator registered for the same prefix
+    pub fn with_operator(mut self, prefix: &[u8], f: MergeFn) -> Self {
+        self.operators.retain(|(p, _)| p.as_slice() != prefix);
+        self.operators.push((prefix.to_vec(), f));
+        self
+    }
+
+    pub fn get(&self, k: &[u8]) -> Option<&MergeFn> {
+        self.operators.iter()
+            .filter(|(p, _)| k.starts_with(p.as_slice()))
+            .max_by_key(|(p, _)| p.len())
+            .map(|(_, f)| f)
+    }
+}
+
+impl std::fmt::Debug for MergeOperators {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.operators.iter().map(|(p, _)| hex::encode(p))).finish()
+    }
+}
+
+/// operators compare by identity, like `ValidationConfig`
+impl PartialEq for MergeOperators {
+    fn eq(&self, other: &Self) -> bool {
+        self.operators.len() == other.operators.len()
+            && self.operators.iter().zip(other.operators.iter()).all(|(a, b)| a.0 == b.0 && Arc::ptr_eq(&a.1, &b.1))
+    }
+}
+
+/// `resolve_with` without merge operators, a `Merge` is rejected
+pub fn resolve<D: DB + ?Sized>(db: &D, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+    resolve_with(db, operations, &MergeOperators::default())
+}
+
+/// lowers increments, merges and range deletes to plain sets and deletes, reading the current
+/// values from `db`. every operation sees the ones before it, the result is sorted by key with
+/// one operation per key, the shape merk expects of a batch. deletes of keys missing from `db`
+/// are dropped, merk refuses them
+pub fn resolve_with<D: DB + ?Sized>(db: &D, operations: Vec<Operation>, operators: &MergeOperators) -> ZKResult<Vec<Operation>> {
+    let mut overlay: BTreeMap<Vec<u8>, Option<Vec<u8>>> = BTreeMap::new();
+    let current = |overlay: &BTreeMap<Vec<u8>, Option<Vec<u8>>>, k: &[u8]| -> ZKResult<Option<Vec<u8>>> {
+        match overlay.get(k) {
+            Some(v) => Ok(v.clone()),
+            None => db.get(k).map(|v| v.map(Cow::into_owned)),
+        }
+    };
+    for op in operations {
+        match op {
+            Operation::Set(k, v) => {
+                overlay.insert(k, Some(v));
+            }
+            Operation::Delete(k) => {
+                overlay.insert(k, None);
+            }
+            Operation::Increment(k, delta) => {
+                let cur = match current(&overlay, k.as_slice())? {
+                    Some(v) => decode_counter(k.as_slice(), v.as_slice())?,
+                    None => 0,
+                };
+                let next = cur.checked_add(delta).ok_or_else(|| {
+                    ZKError::new(ErrorEnumsStruct::ARITHMETIC_OVERFLOW.get_code(),
+                                 format!("key {}: {} + {}", hex::encode(&k), cur, delta))
+                })?;
+                overlay.insert(k, Some(encode_counter(next)));
+            }
+            Operation::Merge(k, operand) => {
+                let f = operators.get(k.as_slice()).ok_or_else(|| {
+                    ZKError::new(ErrorEnumsStruct::OPERATION_INVALID.get_code(),
+                                 format!("no merge operator for key {}", hex::encode(&k)))
+                })?;
+                let cur = current(&overlay, k.as_slice())?;
+                let next = f(k.as_slice(), cur.as_deref(), operand.as_slice())?;
+                overlay.insert(k, Some(next));
+            }
+            Operation::DeleteRange(start, end) => {
+                let bounds = match range_bounds(start.as_slice(), end.as_slice()) {
+                    Some(bounds) => bounds,
+                    None => continue,
+                };
+                let mut keys: Vec<Vec<u8>> = db.range(start.as_slice(), end.as_slice())?.into_iter().map(|(k, _)| k).collect();
+                keys.extend(overlay.range::<[u8], _>(bounds).map(|(k, _)| k.clone()));
+                for k in keys {
+                    overlay.insert(k, None);
+                }
+            }
+        }
+    }
+    let mut resolved = Vec::with_capacity(overlay.len());
+    for (k, v) in overlay {
+        match v {
+            Some(v) => resolved.push(Operation::Set(k, v)),
+            None => {
+                if db.get(k.as_slice())?.is_some() {
+                    resolved.push(Operation::Delete(k));
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use crate::error::ErrorEnumsStruct;
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::operation::{decode_counter, encode_counter, resolve_with, MergeOperators, Operation};
+    use crate::tree::tree::{DB, TreeDB};
+
+    #[test]
+    pub fn test_resolve() {
+        let operators = MergeOperators::new().with_operator(b"log/", Arc::new(|_, cur, operand| {
+            Ok([cur.unwrap_or_default(), operand].concat())
+        }));
+        let mut db = MemoryTreeDB::new();
+        db.commit(vec![
+            Operation::Set(b"bal/a".to_vec(), encode_counter(10)),
+            Operation::Set(b"log/a".to_vec(), vec![1]),
+            Operation::Set(b"ord/1".to_vec(), vec![1]),
+            Operation::Set(b"ord/2".to_vec(), vec![2]),
+            Operation::Set(b"ord/3".to_vec(), vec![3]),
+        ]).expect("fail to commit");
+
+        let ops = resolve_with(&db, vec![
+            Operation::Increment(b"bal/a".to_vec(), -3),
+            Operation::Increment(b"bal/b".to_vec(), 5),
+            Operation::Increment(b"bal/a".to_vec(), 1),
+            Operation::Merge(b"log/a".to_vec(), vec![2]),
+            Operation::Set(b"ord/25".to_vec(), vec![0]),
+            Operation::DeleteRange(b"ord/2".to_vec(), b"ord/3".to_vec()),
+        ], &operators).expect("fail to resolve");
+        assert_eq!(ops, vec![
+            Operation::Set(b"bal/a".to_vec(), encode_counter(8)),
+            Operation::Set(b"bal/b".to_vec(), encode_counter(5)),
+            Operation::Set(b"log/a".to_vec(), vec![1, 2]),
+            Operation::Delete(b"ord/2".to_vec()),
+        ]);
+
+        let ops = resolve_with(&db, vec![
+            Operation::Delete(b"missing".to_vec()),
+            Operation::Set(b"ord/9".to_vec(), vec![9]),
+            Operation::Delete(b"ord/9".to_vec()),
+        ], &operators).expect("fail to resolve");
+        assert!(ops.is_empty());
+
+        db.commit(vec![Operation::Increment(b"bal/a".to_vec(), 1)]).expect("fail to commit");
+        let v = db.get(b"bal/a").unwrap().unwrap();
+        assert_eq!(decode_counter(b"bal/a", &v).unwrap(), 11);
+
+        let err = db.commit(vec![Operation::Increment(b"bal/a".to_vec(), i128::MAX)]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::ARITHMETIC_OVERFLOW.get_code());
+        assert!(db.commit(vec![Operation::Increment(b"ord/1".to_vec(), 1)]).is_err());
+        assert!(db.commit(vec![Operation::Merge(b"ord/1".to_vec(), vec![1])]).is_err());
+    }
 }
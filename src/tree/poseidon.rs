@@ -0,0 +1,330 @@
+use std::sync::OnceLock;
+
+/// BN254 scalar field modulus, little endian limbs
+const MODULUS: [u64; 4] = [0x43e1f593f0000001, 0x2833e84879b97091, 0xb85045b68181585d, 0x30644e72e131a029];
+/// 2^256 mod p
+const R: [u64; 4] = [0xac96341c4ffffffb, 0x36fc76959f60cd29, 0x666ea36f7879462e, 0x0e0a77c19a07df2f];
+/// 2^512 mod p
+const R2: [u64; 4] = [0x1bb8e645ae216da7, 0x53fe3ab1e35c59e3, 0x8c49833d53bb8085, 0x0216d0b17f4e44a5];
+/// -p^-1 mod 2^64
+const INV: u64 = 0xc2e1f593efffffff;
+
+const T: usize = 3;
+const FULL_ROUNDS: usize = 8;
+const PARTIAL_ROUNDS: usize = 57;
+
+/// element of the BN254 scalar field, kept in montgomery form
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Fr([u64; 4]);
+
+impl Fr {
+    pub fn zero() -> Self {
+        Fr([0; 4])
+    }
+
+    pub fn one() -> Self {
+        Fr(R)
+    }
+
+    pub fn from_u64(v: u64) -> Self {
+        Fr::from_raw([v, 0, 0, 0])
+    }
+
+    /// interprets the bytes as a big endian integer and reduces it modulo p
+    pub fn from_be_bytes(bytes: &[u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&bytes[32 - (i + 1) * 8..32 - i * 8]);
+            *limb = u64::from_be_bytes(b);
+        }
+        Fr::from_raw(limbs)
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let limbs = mont_mul(&self.0, &[1, 0, 0, 0]);
+        let mut ret = [0u8; 32];
+        for (i, limb) in limbs.iter().enumerate() {
+            ret[32 - (i + 1) * 8..32 - i * 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        ret
+    }
+
+    fn from_raw(limbs: [u64; 4]) -> Self {
+        Fr(mont_mul(&limbs, &R2))
+    }
+
+    pub fn add(&self, other: &Fr) -> Fr {
+        let (sum, carry) = add_limbs(&self.0, &other.0);
+        if carry || !less_than(&sum, &MODULUS) {
+            Fr(sub_limbs(&sum, &MODULUS))
+        } else {
+            Fr(sum)
+        }
+    }
+
+    pub fn mul(&self, other: &Fr) -> Fr {
+        Fr(mont_mul(&self.0, &other.0))
+    }
+
+    pub fn square(&self) -> Fr {
+        self.mul(self)
+    }
+
+    pub fn pow(&self, exp: &[u64; 4]) -> Fr {
+        let mut ret = Fr::one();
+        for limb in exp.iter().rev() {
+            for i in (0..64).rev() {
+                ret = ret.square();
+                if (limb >> i) & 1 == 1 {
+                    ret = ret.mul(self);
+                }
+            }
+        }
+        ret
+    }
+
+    /// `None` for zero
+    pub fn inverse(&self) -> Option<Fr> {
+        if self.is_zero() {
+            return None;
+        }
+        let exp = sub_limbs(&MODULUS, &[2, 0, 0, 0]);
+        Some(self.pow(&exp))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+}
+
+fn add_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut ret = [0u64; 4];
+    let mut carry = 0u128;
+    for i in 0..4 {
+        let v = a[i] as u128 + b[i] as u128 + carry;
+        ret[i] = v as u64;
+        carry = v >> 64;
+    }
+    (ret, carry != 0)
+}
+
+fn sub_limbs(a: &[u64; 4], b: &[u64; 4]) -> [u64; 4] {
+    let mut ret = [0u64; 4];
+    let mut borrow = 0u64;
+    for i in 0..4 {
+        let (v, b1) = a[i].overflowing_sub(b[i]);
+        let (v, b2) = v.overflowing_sub(borrow);
+        ret[i] = v;
+        borrow = (b1 || b2) as u64;
+    }
+    ret
+}
+
+fn less_than(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    for i in (0..4).rev() {
+        if a[i] != b[i] {
+            return a[i] < b[i];
+        }
+    }
+    false
+}
+
+/// montgomery multiplication, returns a*b/R mod p
+fn mont_mul(a: &[u64; 4], b: &[u64; 4]) -> [u64; 4] {
+    let mut t = [0u64; 6];
+    for bi in b.iter() {
+        let mut carry = 0u128;
+        for j in 0..4 {
+            let v = t[j] as u128 + a[j] as u128 * *bi as u128 + carry;
+            t[j] = v as u64;
+            carry = v >> 64;
+        }
+        let v = t[4] as u128 + carry;
+        t[4] = v as u64;
+        t[5] = (v >> 64) as u64;
+
+        let m = t[0].wrapping_mul(INV);
+        let mut carry = (t[0] as u128 + m as u128 * MODULUS[0] as u128) >> 64;
+        for j in 1..4 {
+            let v = t[j] as u128 + m as u128 * MODULUS[j] as u128 + carry;
+            t[j - 1] = v as u64;
+            carry = v >> 64;
+        }
+        let v = t[4] as u128 + carry;
+        t[3] = v as u64;
+        t[4] = t[5] + (v >> 64) as u64;
+    }
+    let ret = [t[0], t[1], t[2], t[3]];
+    if t[4] != 0 || !less_than(&ret, &MODULUS) {
+        sub_limbs(&ret, &MODULUS)
+    } else {
+        ret
+    }
+}
+
+/// the grain lfsr of the poseidon reference implementation, used to derive round constants and
+/// the mds matrix for (field=1, sbox=0, n=254, t=3, R_F=8, R_P=57), the same parameters circomlib uses
+struct Grain {
+    state: [bool; 80],
+}
+
+impl Grain {
+    fn new() -> Self {
+        let mut bits = Vec::with_capacity(80);
+        let mut push = |v: u64, n: usize| {
+            for i in (0..n).rev() {
+                bits.push((v >> i) & 1 == 1);
+            }
+        };
+        push(1, 2);
+        push(0, 4);
+        push(254, 12);
+        push(T as u64, 12);
+        push(FULL_ROUNDS as u64, 10);
+        push(PARTIAL_ROUNDS as u64, 10);
+        push((1 << 30) - 1, 30);
+        let mut state = [false; 80];
+        state.copy_from_slice(bits.as_slice());
+        let mut ret = Grain { state };
+        for _ in 0..160 {
+            ret.step();
+        }
+        ret
+    }
+
+    fn step(&mut self) -> bool {
+        let s = &self.state;
+        let bit = s[62] ^ s[51] ^ s[38] ^ s[23] ^ s[13] ^ s[0];
+        self.state.copy_within(1.., 0);
+        self.state[79] = bit;
+        bit
+    }
+
+    fn next_bit(&mut self) -> bool {
+        loop {
+            let control = self.step();
+            let bit = self.step();
+            if control {
+                return bit;
+            }
+        }
+    }
+
+    fn next_limbs(&mut self) -> [u64; 4] {
+        let mut limbs = [0u64; 4];
+        for i in (0..254).rev() {
+            if self.next_bit() {
+                limbs[i / 64] |= 1 << (i % 64);
+            }
+        }
+        limbs
+    }
+
+    /// rejection sampled field element
+    fn next_field(&mut self) -> Fr {
+        loop {
+            let limbs = self.next_limbs();
+            if less_than(&limbs, &MODULUS) {
+                return Fr::from_raw(limbs);
+            }
+        }
+    }
+}
+
+struct Constants {
+    round: Vec<Fr>,
+    mds: [[Fr; T]; T],
+}
+
+fn constants() -> &'static Constants {
+    static CONSTANTS: OnceLock<Constants> = OnceLock::new();
+    CONSTANTS.get_or_init(|| {
+        let mut grain = Grain::new();
+        let round = (0..(FULL_ROUNDS + PARTIAL_ROUNDS) * T).map(|_| grain.next_field()).collect();
+        let xs: Vec<Fr> = (0..T * 2).map(|_| Fr::from_raw(grain.next_limbs())).collect();
+        let mut mds = [[Fr::zero(); T]; T];
+        for i in 0..T {
+            for j in 0..T {
+                mds[i][j] = xs[i].add(&xs[T + j]).inverse().expect("cauchy matrix entry is zero");
+            }
+        }
+        Constants { round, mds }
+    })
+}
+
+fn sbox(x: &Fr) -> Fr {
+    let x2 = x.square();
+    x2.square().mul(x)
+}
+
+/// the poseidon permutation over BN254 with width 3 and x^5 sbox
+pub fn permute(state: &mut [Fr; T]) {
+    let c = constants();
+    for r in 0..FULL_ROUNDS + PARTIAL_ROUNDS {
+        for (s, rc) in state.iter_mut().zip(c.round[r * T..(r + 1) * T].iter()) {
+            *s = s.add(rc);
+        }
+        if !(FULL_ROUNDS / 2..FULL_ROUNDS / 2 + PARTIAL_ROUNDS).contains(&r) {
+            for s in state.iter_mut() {
+                *s = sbox(s);
+            }
+        } else {
+            state[0] = sbox(&state[0]);
+        }
+        let mut mixed = [Fr::zero(); T];
+        for (i, m) in mixed.iter_mut().enumerate() {
+            for (mds, s) in c.mds[i].iter().zip(state.iter()) {
+                *m = m.add(&mds.mul(s));
+            }
+        }
+        *state = mixed;
+    }
+}
+
+/// circomlib compatible `Poseidon(2)`
+pub fn hash2(left: &Fr, right: &Fr) -> Fr {
+    let mut state = [Fr::zero(), *left, *right];
+    permute(&mut state);
+    state[0]
+}
+
+/// sponge over arbitrary bytes, absorbs 31 byte chunks two at a time with the byte length as capacity
+pub fn hash_bytes(data: &[u8]) -> Fr {
+    let mut elements: Vec<Fr> = data.chunks(31).map(|chunk| {
+        let mut b = [0u8; 32];
+        b[32 - chunk.len()..].copy_from_slice(chunk);
+        Fr::from_be_bytes(&b)
+    }).collect();
+    if elements.len() % 2 == 1 || elements.is_empty() {
+        elements.push(Fr::zero());
+    }
+    let mut state = [Fr::from_u64(data.len() as u64), Fr::zero(), Fr::zero()];
+    for pair in elements.chunks(2) {
+        state[1] = state[1].add(&pair[0]);
+        state[2] = state[2].add(&pair[1]);
+        permute(&mut state);
+    }
+    state[1]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree::poseidon::{hash2, Fr};
+
+    #[test]
+    pub fn test_field() {
+        let a = Fr::from_u64(7);
+        let inv = a.inverse().unwrap();
+        assert_eq!(a.mul(&inv), Fr::one());
+        let mut minus_one = [0u8; 32];
+        minus_one.copy_from_slice(&hex::decode("30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000000").unwrap());
+        assert_eq!(Fr::from_be_bytes(&minus_one).add(&Fr::one()), Fr::zero());
+    }
+
+    #[test]
+    pub fn test_poseidon_circomlib_vector() {
+        let out = hash2(&Fr::from_u64(1), &Fr::from_u64(2));
+        assert_eq!(hex::encode(out.to_be_bytes()), "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a");
+    }
+}
@@ -0,0 +1,285 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+use crate::tree::memory::MemoryTreeDB;
+use crate::tree::merkle::{verify_merk_proof, MerkleRocksDB};
+use crate::tree::operation::{resolve, Operation};
+use crate::tree::tree::{Visitor, DB, TreeDB};
+
+/// aux key of a sub-tree node, `AUX_NODE | prefix | key`
+const AUX_NODE: u8 = 0;
+/// aux key of a sub-tree root, `AUX_ROOT | prefix`, holding `root hash | root key`
+const AUX_ROOT: u8 = 1;
+
+/// aux entries written next to the data, `None` deletes
+type Aux = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+/// one rocksdb instance holding several logical trees. the data of a tree is stored in merk as
+/// `len(name) | name | key` so names never shadow each other, and each tree keeps its own merk
+/// shaped tree whose nodes go to the aux column family in the same batch, which gives every
+/// sub-tree an independent root
+pub struct PrefixedStore {
+    db: Arc<Mutex<MerkleRocksDB>>,
+    opened: Arc<Mutex<HashSet<String>>>,
+}
+
+impl PrefixedStore {
+    pub fn new(db: MerkleRocksDB) -> Self {
+        Self { db: Arc::new(Mutex::new(db)), opened: Arc::new(Mutex::new(HashSet::new())) }
+    }
+
+    pub fn open<P: AsRef<Path>>(p: P) -> ZKResult<Self> {
+        MerkleRocksDB::new_with_path(p).map(Self::new)
+    }
+
+    /// opens the sub-tree `name`, each name can only be opened once at a time.
+    /// the nodes of the sub-tree are loaded and its root checked against the committed one
+    pub fn tree(&self, name: &str) -> ZKResult<PrefixedTree> {
+        if name.is_empty() || name.len() > u8::MAX as usize {
+            return Err(ZKError::new(ErrorEnumsStruct::PREFIXED_TREE_INVALID.get_code(),
+                                    format!("name must be 1 to 255 bytes,got {}", name.len())));
+        }
+        if !self.opened.lock().unwrap().insert(name.to_string()) {
+            return Err(ZKError::new(ErrorEnumsStruct::PREFIXED_TREE_INVALID.get_code(),
+                                    format!("sub-tree {} is already open", name)));
+        }
+        let mut prefix = vec![name.len() as u8];
+        prefix.extend_from_slice(name.as_bytes());
+        let mut tree = PrefixedTree {
+            name: name.to_string(),
+            prefix,
+            tree: MemoryTreeDB::new(),
+            db: self.db.clone(),
+            opened: self.opened.clone(),
+        };
+        tree.reload()?;
+        Ok(tree)
+    }
+
+    /// applies the operations of several sub-trees in one rocksdb batch, either all of them land or none
+    pub fn commit(&self, mut trees: Vec<(&mut PrefixedTree, Vec<Operation>)>) -> ZKResult<()> {
+        for (tree, _) in trees.iter() {
+            if !Arc::ptr_eq(&tree.db, &self.db) {
+                return Err(ZKError::new(ErrorEnumsStruct::PREFIXED_TREE_INVALID.get_code(),
+                                        format!("sub-tree {} belongs to another store", tree.name)));
+            }
+        }
+        let mut batch = Vec::new();
+        let mut aux = Vec::new();
+        let mut staged = Ok(());
+        for (tree, ops) in trees.iter_mut() {
+            staged = tree.stage(std::mem::take(ops), &mut batch, &mut aux);
+            if staged.is_err() {
+                break;
+            }
+        }
+        // the prefixes keep the sub-trees apart, so the batch is still sorted once merged
+        batch.sort_by(|a, b| a.key().cmp(b.key()));
+        let ret = staged.and_then(|_| self.db.lock().unwrap().commit_with_aux(batch, aux));
+        if ret.is_err() {
+            for (tree, _) in trees.iter_mut() {
+                let _ = tree.reload();
+            }
+        }
+        ret
+    }
+
+    /// root of the physical tree, covering the data of every sub-tree
+    pub fn root_hash(&self) -> [u8; 32] {
+        self.db.lock().unwrap().root_hash()
+    }
+}
+
+/// `TreeDB` view of one sub-tree with its own root. reads and proofs are served by the nodes of
+/// the sub-tree, which are kept in memory while it is open, writes go to merk with the keys
+/// prefixed together with the changed nodes
+pub struct PrefixedTree {
+    name: String,
+    prefix: Vec<u8>,
+    tree: MemoryTreeDB,
+    db: Arc<Mutex<MerkleRocksDB>>,
+    opened: Arc<Mutex<HashSet<String>>>,
+}
+
+impl PrefixedTree {
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn key(&self, k: &[u8]) -> Vec<u8> {
+        [self.prefix.as_slice(), k].concat()
+    }
+
+    fn aux_key(&self, tag: u8, k: &[u8]) -> Vec<u8> {
+        [&[tag][..], self.prefix.as_slice(), k].concat()
+    }
+
+    /// only takes resolved operations, the view has to be consulted for anything else
+    fn prefixed(&self, op: &Operation) -> Operation {
+        match op {
+            Operation::Set(k, v) => Operation::Set(self.key(k), v.clone()),
+            Operation::Delete(k) => Operation::Delete(self.key(k)),
+            _ => unreachable!("operations are resolved before they are prefixed"),
+        }
+    }
+
+    /// applies the operations to the in-memory nodes and adds what has to be written to the
+    /// store to `batch` and `aux`. the nodes are ahead of the store until the batch lands,
+    /// `reload` brings them back if it does not
+    fn stage(&mut self, operations: Vec<Operation>, batch: &mut Vec<Operation>, aux: &mut Aux) -> ZKResult<()> {
+        let operations = resolve(&self.tree, operations)?;
+        if operations.is_empty() {
+            return Ok(());
+        }
+        for op in operations.iter() {
+            if let Operation::Delete(k) = op {
+                aux.push((self.aux_key(AUX_NODE, k), None));
+            }
+            batch.push(self.prefixed(op));
+        }
+        self.tree.apply(operations);
+
+        let mut nodes = Vec::new();
+        self.tree.take_dirty(&mut |k, node| nodes.push((k.to_vec(), node)));
+        aux.extend(nodes.into_iter().map(|(k, node)| (self.aux_key(AUX_NODE, k.as_slice()), Some(node))));
+        let root = self.tree.root_key().map(|k| [&self.tree.root_hash()[..], k].concat());
+        aux.push((self.aux_key(AUX_ROOT, &[]), root));
+        Ok(())
+    }
+
+    /// replaces the in-memory nodes with the ones committed to the store
+    fn reload(&mut self) -> ZKResult<()> {
+        let db = self.db.lock().unwrap();
+        let root = db.get_aux(self.aux_key(AUX_ROOT, &[]).as_slice())?;
+        let (expected, root_key) = match root.as_deref() {
+            Some(r) if r.len() > 32 => (r[..32].to_vec(), Some(&r[32..])),
+            Some(_) => return Err(ZKError::new(ErrorEnumsStruct::PREFIXED_TREE_INVALID.get_code(),
+                                               format!("sub-tree {} has a malformed root", self.name))),
+            None => (MemoryTreeDB::new().root_hash().to_vec(), None),
+        };
+        let tree = MemoryTreeDB::load(root_key, &mut |k| db.get_aux(self.aux_key(AUX_NODE, k).as_slice()),
+                                      ErrorEnumsStruct::PREFIXED_TREE_INVALID)?;
+        if tree.root_hash()[..] != expected[..] {
+            return Err(ZKError::new(ErrorEnumsStruct::PREFIXED_TREE_INVALID.get_code(),
+                                    format!("sub-tree {} does not match its committed root", self.name)));
+        }
+        drop(db);
+        self.tree = tree;
+        Ok(())
+    }
+}
+
+impl Drop for PrefixedTree {
+    fn drop(&mut self) {
+        self.opened.lock().unwrap().remove(&self.name);
+    }
+}
+
+impl DB for PrefixedTree {
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        self.tree.get(k)
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        let old = self.get(k)?.map(Cow::into_owned);
+        self.commit(vec![Operation::Set(k.to_vec(), v)])?;
+        Ok(old)
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        let old = self.get(k)?.map(Cow::into_owned);
+        if old.is_some() {
+            self.commit(vec![Operation::Delete(k.to_vec())])?;
+        }
+        Ok(old)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.tree.range(start, end)
+    }
+
+    fn scan(&self, start: &[u8], end: &[u8], f: &mut Visitor) -> ZKResult<()> {
+        self.tree.scan(start, end, f)
+    }
+}
+
+impl TreeDB for PrefixedTree {
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        self.tree.prove(req)
+    }
+
+    /// proofs verify against the root of the sub-tree, not the one of the store
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        verify_merk_proof(req)
+    }
+
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        let mut batch = Vec::new();
+        let mut aux = Vec::new();
+        let mut ret = self.stage(operations, &mut batch, &mut aux);
+        if ret.is_ok() && !batch.is_empty() {
+            ret = self.db.lock().unwrap().commit_with_aux(batch, aux);
+        }
+        if ret.is_err() {
+            let _ = self.reload();
+        }
+        ret
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        self.tree.root_hash()
+    }
+}
+
+#[test]
+pub fn test_prefixed_trees() {
+    let path = std::env::temp_dir().join(format!("zkp_prefixed_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let store = PrefixedStore::open(&path).expect("fail to open");
+    let mut acct = store.tree("acct").expect("fail to open acct");
+    let mut order = store.tree("order").expect("fail to open order");
+    assert!(store.tree("acct").is_err());
+
+    acct.set(&[1], vec![1]).expect("fail to set");
+    let acct_root = acct.root_hash();
+    let order_root = order.root_hash();
+    store.commit(vec![
+        (&mut order, vec![Operation::Set(vec![1], vec![2])]),
+    ]).expect("fail to commit");
+    assert_eq!(acct.root_hash(), acct_root);
+    assert_ne!(order.root_hash(), order_root);
+    assert_ne!(acct.root_hash(), order.root_hash());
+
+    let order_root = order.root_hash();
+    store.commit(vec![
+        (&mut order, vec![]),
+        (&mut acct, vec![Operation::Set(vec![2], vec![2])]),
+    ]).expect("fail to commit");
+    assert_ne!(acct.root_hash(), acct_root);
+    assert_eq!(order.root_hash(), order_root);
+    assert_ne!(acct.root_hash(), store.root_hash());
+    assert_eq!(order.get_many(&[&[1], &[2]]).unwrap(), vec![Some(vec![2]), None]);
+    assert_eq!(acct.get_many(&[&[1]]).unwrap(), vec![Some(vec![1])]);
+    assert_eq!(acct.range(&[], &[]).unwrap(), vec![(vec![1], vec![1]), (vec![2], vec![2])]);
+
+    let mut req = ProveRequest::default();
+    req.insert(vec![1]);
+    let proof = order.prove(req).expect("fail to prove").proof;
+    let mut v_req = VerifyRequest::new(proof.clone(), order.root_hash());
+    v_req.insert(vec![1], vec![2]);
+    assert!(order.verify(v_req).expect("fail to verify").valid);
+    let mut v_req = VerifyRequest::new(proof, order.root_hash());
+    v_req.insert(vec![1], vec![1]);
+    assert!(!order.verify(v_req).expect("fail to verify").valid);
+
+    let root = acct.root_hash();
+    drop(acct);
+    let acct = store.tree("acct").expect("fail to reopen acct");
+    assert_eq!(acct.root_hash(), root);
+    assert_eq!(acct.get(&[2]).unwrap().as_deref(), Some(&[2u8][..]));
+    drop((acct, order, store));
+    std::fs::remove_dir_all(&path).expect("fail to remove");
+}
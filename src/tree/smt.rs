@@ -1,153 +1,507 @@
-use hash256_std_hasher::Hash256StdHasher;
-use hash_db::{AsHashDB, HashDB, Hasher, Prefix};
-use memory_db::prefixed_key;
-use merk::{Merk, Op};
-
-use sparse_merkle_tree::{
-    blake2b::Blake2bHasher, default_store::DefaultStore,
-    error::Error, MerkleProof,
-    SparseMerkleTree, traits::Value, H256,
-};
-use tiny_keccak::{Hasher as KeccaHasher, Keccak};
-use crate::error::{ZKError, ZKResult};
-use crate::middleware::middleware::TreeMiddleware;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::path::Path;
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
 use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
-use crate::tree::operation::Operation;
-use crate::tree::tree::{DB, TreeDB};
+use crate::tree::hasher::{KeccakHasher, TreeHasher};
+use crate::tree::merkle::MerkleRocksDB;
+use crate::tree::operation::{resolve, Operation};
+use crate::tree::tree::{range_bounds, Visitor, DB, TreeDB};
 
-pub struct SMTMiddleware<M>
-    where
-        M: TreeMiddleware {
-    inner: M,
+pub type H256 = [u8; 32];
+
+pub const ZERO_HASH: H256 = [0u8; 32];
+
+pub const SMT_DEPTH: usize = 256;
+
+/// bit `i` of the path, counted from the most significant bit
+fn path_bit(path: &H256, i: usize) -> bool {
+    (path[i / 8] >> (7 - i % 8)) & 1 == 1
+}
+
+/// first bit, counted from the most significant one, where the paths differ
+fn first_diff(a: &H256, b: &H256) -> Option<usize> {
+    a.iter().zip(b.iter()).position(|(x, y)| x != y).map(|i| i * 8 + (a[i] ^ b[i]).leading_zeros() as usize)
+}
+
+fn bitmap_get(bitmap: &H256, height: usize) -> bool {
+    (bitmap[31 - height / 8] >> (height % 8)) & 1 == 1
+}
+
+fn bitmap_set(bitmap: &mut H256, height: usize) {
+    bitmap[31 - height / 8] |= 1 << (height % 8);
+}
+
+/// empty subtrees hash to zero at every height
+pub fn merge<H: TreeHasher>(left: &H256, right: &H256) -> H256 {
+    if *left == ZERO_HASH && *right == ZERO_HASH {
+        return ZERO_HASH;
+    }
+    H::hash_pair(left, right)
 }
 
-// TODO, wrapped with db
-pub struct SMTreeDB {
-    m: Merk,
+pub fn leaf_hash<H: TreeHasher>(path: &H256, value: Option<&[u8]>) -> H256 {
+    match value {
+        Some(v) => H::hash_pair(path, &H::hash(v)),
+        None => ZERO_HASH,
+    }
 }
 
-impl SMTreeDB {
-    fn extend_prefix<H: Hasher>(&self, key: &H::Out, prefix: Prefix) -> Vec<u8> {
-        prefixed_key::<H>(key, prefix)
+/// hash of a subtree at height `from` carried up to `to` through levels where its sibling is empty
+fn lift<H: TreeHasher>(path: &H256, mut hash: H256, from: usize, to: usize) -> H256 {
+    for height in from..to {
+        hash = if path_bit(path, SMT_DEPTH - 1 - height) {
+            merge::<H>(&ZERO_HASH, &hash)
+        } else {
+            merge::<H>(&hash, &ZERO_HASH)
+        };
     }
+    hash
+}
+
+/// node of the compressed tree, a leaf at height 0 or a branch at the height where the paths
+/// below it split. the levels skipped in between have an empty sibling and are not stored
+struct Node {
+    height: usize,
+    /// path of a leaf below, the bits above `height` are the same for the whole subtree
+    path: H256,
+    hash: H256,
+    /// left and right child of a branch, with their hash lifted to `height - 1`
+    children: Option<Box<[(Node, H256); 2]>>,
 }
 
-pub type KeccakHash = [u8; 32];
+impl Node {
+    fn leaf(path: H256, hash: H256) -> Node {
+        Node { height: 0, path, hash, children: None }
+    }
 
-#[derive(Default, Debug, Clone, PartialEq)]
-pub struct KeccakHasher;
+    fn lifted<H: TreeHasher>(self, height: usize) -> (Node, H256) {
+        let hash = lift::<H>(&self.path, self.hash, self.height, height - 1);
+        (self, hash)
+    }
 
-impl Hasher for KeccakHasher {
-    type Out = KeccakHash;
+    fn join<H: TreeHasher>(left: (Node, H256), right: (Node, H256), height: usize) -> Node {
+        Node { height, path: left.0.path, hash: merge::<H>(&left.1, &right.1), children: Some(Box::new([left, right])) }
+    }
 
-    type StdHasher = Hash256StdHasher;
+    /// the bit where `path` leaves this subtree, `None` when it lies inside
+    fn diverges(&self, path: &H256) -> Option<usize> {
+        first_diff(&self.path, path).filter(|d| *d < SMT_DEPTH - self.height)
+    }
 
-    const LENGTH: usize = 32;
+    /// only the branches on the way down are rebuilt, the other child keeps its lifted hash
+    fn insert<H: TreeHasher>(self, path: &H256, leaf: H256) -> Node {
+        if let Some(d) = self.diverges(path) {
+            let height = SMT_DEPTH - d;
+            let (old, new) = (self.lifted::<H>(height), Node::leaf(*path, leaf).lifted::<H>(height));
+            return match path_bit(path, d) {
+                true => Node::join::<H>(old, new, height),
+                false => Node::join::<H>(new, old, height),
+            };
+        }
+        let height = self.height;
+        match self.children {
+            None => Node::leaf(*path, leaf),
+            Some(children) => {
+                let [left, right] = *children;
+                match path_bit(path, SMT_DEPTH - height) {
+                    true => Node::join::<H>(left, right.0.insert::<H>(path, leaf).lifted::<H>(height), height),
+                    false => Node::join::<H>(left.0.insert::<H>(path, leaf).lifted::<H>(height), right, height),
+                }
+            }
+        }
+    }
 
-    fn hash(x: &[u8]) -> Self::Out {
-        let mut keccak = Keccak::v256();
-        keccak.update(x);
-        let mut out = [0u8; 32];
-        keccak.finalize(&mut out);
-        out
+    /// a branch left with one child is replaced by that child
+    fn remove<H: TreeHasher>(self, path: &H256) -> Option<Node> {
+        if self.diverges(path).is_some() {
+            return Some(self);
+        }
+        let height = self.height;
+        let [left, right] = *self.children?;
+        Some(match path_bit(path, SMT_DEPTH - height) {
+            true => match right.0.remove::<H>(path) {
+                Some(right) => Node::join::<H>(left, right.lifted::<H>(height), height),
+                None => left.0,
+            },
+            false => match left.0.remove::<H>(path) {
+                Some(left) => Node::join::<H>(left.lifted::<H>(height), right, height),
+                None => right.0,
+            },
+        })
     }
 }
 
+/// merkle path of one key: bit `h` of the big endian `bitmap` is set when the sibling at height
+/// `h` is not the empty subtree, only those siblings are kept, ordered from the leaf upwards
+#[derive(Debug, Clone, PartialEq)]
+pub struct SMTProof {
+    pub path: H256,
+    pub bitmap: H256,
+    pub siblings: Vec<H256>,
+}
+
+impl SMTProof {
+    pub fn compute_root<H: TreeHasher>(&self, leaf: &H256) -> ZKResult<H256> {
+        let mut cur = *leaf;
+        let mut siblings = self.siblings.iter();
+        for height in 0..SMT_DEPTH {
+            let sibling = if bitmap_get(&self.bitmap, height) {
+                *siblings.next().ok_or_else(|| ZKError::from(ErrorEnumsStruct::SMT_INVALID_PROOF))?
+            } else {
+                ZERO_HASH
+            };
+            cur = if path_bit(&self.path, SMT_DEPTH - 1 - height) {
+                merge::<H>(&sibling, &cur)
+            } else {
+                merge::<H>(&cur, &sibling)
+            };
+        }
+        if siblings.next().is_some() {
+            return Err(ZKError::from(ErrorEnumsStruct::SMT_INVALID_PROOF));
+        }
+        Ok(cur)
+    }
 
-impl AsHashDB<KeccakHasher, Vec<u8>> for SMTreeDB {
-    fn as_hash_db(&self) -> &dyn HashDB<KeccakHasher, Vec<u8>> {
-        self
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.path);
+        buf.extend_from_slice(&self.bitmap);
+        for s in self.siblings.iter() {
+            buf.extend_from_slice(s);
+        }
     }
 
-    fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<KeccakHasher, Vec<u8>> + 'a) {
-        self
+    /// proofs are encoded as `count(u32) | (path | bitmap | siblings)*`
+    pub fn decode_all(data: &[u8]) -> ZKResult<Vec<SMTProof>> {
+        let invalid = || ZKError::from(ErrorEnumsStruct::SMT_INVALID_PROOF);
+        if data.len() < 4 {
+            return Err(invalid());
+        }
+        let count = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let mut offset = 4;
+        let mut take = || -> ZKResult<H256> {
+            if offset + 32 > data.len() {
+                return Err(invalid());
+            }
+            let mut ret = ZERO_HASH;
+            ret.copy_from_slice(&data[offset..offset + 32]);
+            offset += 32;
+            Ok(ret)
+        };
+        let mut ret = Vec::with_capacity(count);
+        for _ in 0..count {
+            let path = take()?;
+            let bitmap = take()?;
+            let n = bitmap.iter().map(|b| b.count_ones() as usize).sum();
+            let mut siblings = Vec::with_capacity(n);
+            for _ in 0..n {
+                siblings.push(take()?);
+            }
+            ret.push(SMTProof { path, bitmap, siblings });
+        }
+        if offset != data.len() {
+            return Err(invalid());
+        }
+        Ok(ret)
     }
 }
 
-//
-impl HashDB<KeccakHasher, Vec<u8>> for SMTreeDB {
-    fn get(&self, key: &<KeccakHasher as Hasher>::Out, prefix: Prefix) -> Option<Vec<u8>> {
-        let k = self.extend_prefix::<KeccakHasher>(key, prefix);
-        self.m.get(k.as_slice()).map_or_else(|e| {
-            None
-        }, |v| {
-            v
-        })
+/// sparse merkle tree of depth 256, a key lives at the leaf `H::hash(key)`.
+/// the tree is kept in memory compressed to its leaves and the branches where their paths split,
+/// leaves are optionally persisted into a merk store
+pub struct SMTreeDB<H: TreeHasher = KeccakHasher> {
+    leaves: BTreeMap<Vec<u8>, Vec<u8>>,
+    root: Option<Node>,
+    root_hash: H256,
+    store: Option<MerkleRocksDB>,
+    _hasher: PhantomData<H>,
+}
+
+unsafe impl<H: TreeHasher> Send for SMTreeDB<H> {}
+
+unsafe impl<H: TreeHasher> Sync for SMTreeDB<H> {}
+
+impl<H: TreeHasher> Default for SMTreeDB<H> {
+    fn default() -> Self {
+        Self { leaves: BTreeMap::new(), root: None, root_hash: ZERO_HASH, store: None, _hasher: PhantomData }
     }
+}
 
-    fn contains(&self, key: &<KeccakHasher as Hasher>::Out, prefix: Prefix) -> bool {
-        self.get(key, prefix).map_or_else(|| { false }, |v| {
-            true
-        })
+impl<H: TreeHasher> SMTreeDB<H> {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    fn insert(&mut self, _: Prefix, value: &[u8]) -> <KeccakHasher as Hasher>::Out {
-        let key = KeccakHasher::hash(value);
-        self.m.apply(&vec![(key.to_vec(), Op::Put(value.to_vec()))], &[]).expect("fail to set");
-        key
+    /// leaves are persisted at `p` and the tree is rebuilt from them on open
+    pub fn new_with_path<P: AsRef<Path>>(p: P) -> ZKResult<Self> {
+        let store = MerkleRocksDB::new_with_path(p)?;
+        let mut entries = Vec::new();
+        store.for_each_entry(|k, v| entries.push((k.to_vec(), v.to_vec())))?;
+        let mut ret = Self::default();
+        for (k, v) in entries {
+            ret.update(k, Some(v));
+        }
+        ret.store = Some(store);
+        Ok(ret)
     }
 
-    fn emplace(&mut self, key: <KeccakHasher as Hasher>::Out, prefix: Prefix, value: Vec<u8>) {
-        let key = self.extend_prefix::<KeccakHasher>(&key, prefix);
-        self.m.apply(&vec![(key, Op::Put(value))], &[]).expect("fail to set");
+    /// returns the previous leaf value
+    fn update(&mut self, k: Vec<u8>, v: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        let path = H::hash(k.as_slice());
+        let root = self.root.take();
+        self.root = match v.as_deref() {
+            Some(value) => {
+                let leaf = leaf_hash::<H>(&path, Some(value));
+                Some(match root {
+                    Some(root) => root.insert::<H>(&path, leaf),
+                    None => Node::leaf(path, leaf),
+                })
+            }
+            None if self.leaves.contains_key(&k) => root.and_then(|root| root.remove::<H>(&path)),
+            None => root,
+        };
+        self.root_hash = self.root.as_ref().map_or(ZERO_HASH, |r| lift::<H>(&r.path, r.hash, r.height, SMT_DEPTH));
+        match v {
+            Some(value) => self.leaves.insert(k, value),
+            None => self.leaves.remove(&k),
+        }
     }
 
-    fn remove(&mut self, key: &<KeccakHasher as Hasher>::Out, prefix: Prefix) {
-        let key = self.extend_prefix::<KeccakHasher>(&key, prefix);
-        self.m.apply(&vec![(key, Op::Delete)], &[]).expect("fail to delete")
+    /// walks down the branches along the path, every branch passed holds a sibling and the node
+    /// the path leaves the tree at is the last one. the other levels have an empty sibling
+    pub fn merkle_proof(&self, k: &[u8]) -> SMTProof {
+        let path = H::hash(k);
+        let mut found = Vec::new();
+        let mut cur = self.root.as_ref();
+        while let Some(node) = cur {
+            if let Some(d) = node.diverges(&path) {
+                let height = SMT_DEPTH - 1 - d;
+                found.push((height, lift::<H>(&node.path, node.hash, node.height, height)));
+                break;
+            }
+            cur = node.children.as_ref().map(|children| {
+                let bit = path_bit(&path, SMT_DEPTH - node.height) as usize;
+                found.push((node.height - 1, children[1 - bit].1));
+                &children[bit].0
+            });
+        }
+        let mut bitmap = ZERO_HASH;
+        let mut siblings = Vec::with_capacity(found.len());
+        for (height, sibling) in found.into_iter().rev() {
+            bitmap_set(&mut bitmap, height);
+            siblings.push(sibling);
+        }
+        SMTProof { path, bitmap, siblings }
     }
 }
 
+impl<H: TreeHasher> DB for SMTreeDB<H> {
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        Ok(self.leaves.get(k).map(|v| Cow::Borrowed(v.as_slice())))
+    }
+
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        check_value(k, v.as_slice())?;
+        if let Some(store) = self.store.as_mut() {
+            store.set(k, v.clone())?;
+        }
+        Ok(self.update(k.to_vec(), Some(v)))
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        if let Some(store) = self.store.as_mut() {
+            store.delete(k)?;
+        }
+        Ok(self.update(k.to_vec(), None))
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(match range_bounds(start, end) {
+            Some(bounds) => self.leaves.range::<[u8], _>(bounds).map(|(k, v)| (k.clone(), v.clone())).collect(),
+            None => vec![],
+        })
+    }
+
+    fn scan(&self, start: &[u8], end: &[u8], f: &mut Visitor) -> ZKResult<()> {
+        if let Some(bounds) = range_bounds(start, end) {
+            for (k, v) in self.leaves.range::<[u8], _>(bounds) {
+                f(k.as_slice(), v.as_slice())?;
+            }
+        }
+        Ok(())
+    }
+}
 
-unsafe impl Send for SMTreeDB {}
+/// an empty leaf is the absent key, so an empty value could not be told apart from a missing one
+fn check_value(k: &[u8], v: &[u8]) -> ZKResult<()> {
+    if v.is_empty() {
+        return Err(ZKError::new(ErrorEnumsStruct::OPERATION_INVALID.get_code(),
+                                format!("empty value for key {},the sparse tree can not store it", hex::encode(k))));
+    }
+    Ok(())
+}
 
-unsafe impl Sync for SMTreeDB {}
+/// checks proofs produced by `SMTreeDB::<H>::prove`, an empty expected value proves the key is absent.
+/// a request without any key proves nothing and is invalid
+pub fn verify_smt_proof<H: TreeHasher>(req: VerifyRequest) -> ZKResult<VerifyResponse> {
+    let proofs = SMTProof::decode_all(req.proof.as_slice())?;
+    let mut ret = VerifyResponse::default();
+    if req.kv.is_empty() {
+        return Ok(ret);
+    }
+    for (k, v) in req.kv {
+        let path = H::hash(k.as_slice());
+        let proof = match proofs.iter().find(|p| p.path == path) {
+            Some(p) => p,
+            None => return Ok(ret),
+        };
+        let value = if v.is_empty() { None } else { Some(v.as_slice()) };
+        if proof.compute_root::<H>(&leaf_hash::<H>(&path, value))? != req.expected_root {
+            return Ok(ret);
+        }
+    }
+    ret.valid = true;
+    Ok(ret)
+}
 
-impl<M> TreeDB for SMTMiddleware<M> where M: TreeMiddleware {
+impl<H: TreeHasher> TreeDB for SMTreeDB<H> {
     fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
-        todo!()
+        let mut proof = Vec::new();
+        proof.extend_from_slice(&(req.query.len() as u32).to_be_bytes());
+        for k in req.query.iter() {
+            self.merkle_proof(k).encode_into(&mut proof);
+        }
+        Ok(ProveResponse { proof })
     }
 
     fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
-        todo!()
+        verify_smt_proof::<H>(req)
     }
 
     fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
-        todo!()
+        let operations = resolve(self, operations)?;
+        for op in operations.iter() {
+            if let Operation::Set(k, v) = op {
+                check_value(k.as_slice(), v.as_slice())?;
+            }
+        }
+        if let Some(store) = self.store.as_mut() {
+            store.commit(operations.clone())?;
+        }
+        for op in operations {
+            match op {
+                Operation::Set(k, v) => self.update(k, Some(v)),
+                Operation::Delete(k) => self.update(k, None),
+                _ => unreachable!("operations are resolved before they are applied"),
+            };
+        }
+        Ok(())
     }
 
     fn root_hash(&self) -> [u8; 32] {
-        todo!()
+        self.root_hash
     }
 }
 
-impl<M> DB for SMTMiddleware<M> where M: TreeMiddleware {
-    fn get(&self, k: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
-        todo!()
+#[cfg(test)]
+mod test {
+    use crate::error::ErrorEnumsStruct;
+    use crate::tree::couple::{ProveRequest, VerifyRequest};
+    use crate::tree::hasher::{Blake2bHasher, KeccakHasher, PoseidonHasher, TreeHasher};
+    use crate::tree::operation::Operation;
+    use crate::tree::smt::{leaf_hash, merge, path_bit, SMTreeDB, H256, SMT_DEPTH, ZERO_HASH};
+    use crate::tree::tree::{DB, TreeDB};
+
+    fn prove_verify<H: TreeHasher>() {
+        let mut smt = SMTreeDB::<H>::new();
+        smt.commit(vec![
+            Operation::Set(vec![1], vec![1, 1]),
+            Operation::Set(vec![2], vec![2, 2]),
+            Operation::Set(vec![3], vec![3, 3]),
+        ]).expect("fail to commit");
+        let root = smt.root_hash();
+
+        let mut req = ProveRequest::default();
+        req.insert(vec![2]);
+        req.insert(vec![9]);
+        let proof = smt.prove(req).expect("fail to prove").proof;
+
+        let mut v_req = VerifyRequest::new(proof.clone(), root);
+        v_req.insert(vec![2], vec![2, 2]);
+        v_req.insert(vec![9], vec![]);
+        assert!(smt.verify(v_req).expect("fail to verify").valid);
+
+        let mut v_req = VerifyRequest::new(proof.clone(), root);
+        v_req.insert(vec![9], vec![9]);
+        assert!(!smt.verify(v_req).expect("fail to verify").valid);
+        assert!(!smt.verify(VerifyRequest::new(proof, root)).expect("fail to verify").valid);
     }
 
-    fn set(&mut self, k: Vec<u8>, v: Vec<u8>) -> ZKResult<Vec<u8>> {
-        todo!()
+    #[test]
+    pub fn test_prove_verify() {
+        prove_verify::<KeccakHasher>();
+        prove_verify::<Blake2bHasher>();
+        prove_verify::<PoseidonHasher>();
     }
 
-    fn delete(&mut self, k: Vec<u8>) -> ZKResult<()> {
-        todo!()
+    #[test]
+    pub fn test_root_independent_of_order() {
+        let mut a = SMTreeDB::<KeccakHasher>::new();
+        let mut b = SMTreeDB::<KeccakHasher>::new();
+        for i in 0..16u8 {
+            a.set(&[i], vec![i]).unwrap();
+            b.set(&[15 - i], vec![15 - i]).unwrap();
+        }
+        assert_eq!(a.root_hash(), b.root_hash());
+        for i in 0..16u8 {
+            a.delete(&[i]).unwrap();
+        }
+        assert_eq!(a.root_hash(), ZERO_HASH);
     }
-}
 
-impl<M> TreeMiddleware for SMTMiddleware<M>
-    where
-        M: TreeMiddleware,
-{
-    type Inner = M;
+    #[test]
+    pub fn test_rejects_empty_values() {
+        let mut smt = SMTreeDB::<KeccakHasher>::new();
+        let err = smt.set(&[1], vec![]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::OPERATION_INVALID.get_code());
+        let err = smt.commit(vec![Operation::Set(vec![2], vec![2]), Operation::Set(vec![3], vec![])]).unwrap_err();
+        assert_eq!(err.get_code(), ErrorEnumsStruct::OPERATION_INVALID.get_code());
+        assert_eq!(smt.root_hash(), ZERO_HASH);
+        assert!(smt.get(&[2]).unwrap().is_none());
+    }
 
-    fn inner(&self) -> &Self::Inner {
-        &self.inner
+    /// the uncompressed tree, every level of it
+    fn full_root(leaves: &[(H256, H256)], height: usize) -> H256 {
+        if leaves.is_empty() || height == 0 {
+            return leaves.first().map_or(ZERO_HASH, |l| l.1);
+        }
+        let (right, left): (Vec<_>, Vec<_>) = leaves.iter().partition(|l| path_bit(&l.0, SMT_DEPTH - height));
+        merge::<KeccakHasher>(&full_root(&left, height - 1), &full_root(&right, height - 1))
     }
-}
 
-#[cfg(test)]
-mod test {
     #[test]
-    pub fn test_st() {}
-}
\ No newline at end of file
+    pub fn test_matches_full_tree() {
+        let mut smt = SMTreeDB::<KeccakHasher>::new();
+        let mut leaves = Vec::new();
+        for i in 0..64u8 {
+            smt.set(&[i], vec![i; 3]).unwrap();
+        }
+        for i in (0..64u8).step_by(3) {
+            smt.delete(&[i]).unwrap();
+        }
+        smt.delete(&[200]).unwrap();
+        for i in (0..64u8).filter(|i| i % 3 != 0) {
+            let path = KeccakHasher::hash(&[i]);
+            leaves.push((path, leaf_hash::<KeccakHasher>(&path, Some(&[i; 3]))));
+        }
+        let root = smt.root_hash();
+        assert_eq!(root, full_root(&leaves, SMT_DEPTH));
+
+        for i in 0..70u8 {
+            let value = smt.get(&[i]).unwrap().map(|v| v.into_owned());
+            let leaf = leaf_hash::<KeccakHasher>(&KeccakHasher::hash(&[i]), value.as_deref());
+            assert_eq!(smt.merkle_proof(&[i]).compute_root::<KeccakHasher>(&leaf).expect("fail to compute"), root);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use tiny_keccak::{Hasher, Keccak};
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::tree::hasher::KeccakHasher;
+use crate::tree::smt::{SMTreeDB, H256, SMT_DEPTH, ZERO_HASH};
+use crate::tree::tree::{DB, TreeDB};
+
+pub const VERIFY_SIGNATURE: &str = "verifyProof(bytes32,bytes,bytes,uint256,bytes32[])";
+
+/// the on-chain side of `SolidityProof`, `SolidityProof::verify` follows it step by step
+pub const SOLIDITY_VERIFIER: &str = r#"// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+contract SMTVerifier {
+    function merge(bytes32 l, bytes32 r) internal pure returns (bytes32) {
+        if (l == bytes32(0) && r == bytes32(0)) {
+            return bytes32(0);
+        }
+        return keccak256(abi.encodePacked(l, r));
+    }
+
+    /// an empty value proves the key is absent
+    function verifyProof(
+        bytes32 root,
+        bytes calldata key,
+        bytes calldata value,
+        uint256 bitmap,
+        bytes32[] calldata siblings
+    ) external pure returns (bool) {
+        bytes32 path = keccak256(key);
+        bytes32 node = value.length == 0 ? bytes32(0) : keccak256(abi.encodePacked(path, keccak256(value)));
+        uint256 j = 0;
+        for (uint256 h = 0; h < 256; h++) {
+            bytes32 sibling = bytes32(0);
+            if ((bitmap >> h) & 1 == 1) {
+                if (j == siblings.length) {
+                    return false;
+                }
+                sibling = siblings[j++];
+            }
+            if ((uint256(path) >> h) & 1 == 1) {
+                node = merge(sibling, node);
+            } else {
+                node = merge(node, sibling);
+            }
+        }
+        return j == siblings.length && node == root;
+    }
+}
+"#;
+
+fn keccak(data: &[u8]) -> H256 {
+    let mut keccak = Keccak::v256();
+    keccak.update(data);
+    let mut out = [0u8; 32];
+    keccak.finalize(&mut out);
+    out
+}
+
+/// bit `i` of a big-endian `uint256`, counted from the least significant bit
+fn uint_bit(v: &H256, i: usize) -> bool {
+    (v[31 - i / 8] >> (i % 8)) & 1 == 1
+}
+
+fn merge(l: &H256, r: &H256) -> H256 {
+    if *l == ZERO_HASH && *r == ZERO_HASH {
+        return ZERO_HASH;
+    }
+    keccak(&[&l[..], &r[..]].concat())
+}
+
+pub fn selector() -> [u8; 4] {
+    let mut ret = [0u8; 4];
+    ret.copy_from_slice(&keccak(VERIFY_SIGNATURE.as_bytes())[..4]);
+    ret
+}
+
+/// inclusion or exclusion proof of one key of a keccak SMT, in the shape `verifyProof` takes.
+/// an empty value proves the key is absent, as in `SMTreeDB::verify`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolidityProof {
+    pub root: H256,
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    /// `uint256` whose bit `h` is set when the sibling at height `h` is carried
+    pub bitmap: H256,
+    /// non empty siblings from the leaf upwards
+    pub siblings: Vec<H256>,
+}
+
+impl SolidityProof {
+    pub fn from_smt(smt: &SMTreeDB<KeccakHasher>, k: &[u8]) -> ZKResult<Self> {
+        let proof = smt.merkle_proof(k);
+        Ok(Self {
+            root: smt.root_hash(),
+            key: k.to_vec(),
+            value: smt.get(k)?.map(|v| v.into_owned()).unwrap_or_default(),
+            bitmap: proof.bitmap,
+            siblings: proof.siblings,
+        })
+    }
+
+    /// reference verifier, the same steps as the contract
+    pub fn verify(&self) -> bool {
+        let path = keccak(self.key.as_slice());
+        let mut node = if self.value.is_empty() {
+            ZERO_HASH
+        } else {
+            keccak(&[&path[..], &keccak(self.value.as_slice())[..]].concat())
+        };
+        let mut j = 0;
+        for h in 0..SMT_DEPTH {
+            let mut sibling = ZERO_HASH;
+            if uint_bit(&self.bitmap, h) {
+                if j == self.siblings.len() {
+                    return false;
+                }
+                sibling = self.siblings[j];
+                j += 1;
+            }
+            node = if uint_bit(&path, h) {
+                merge(&sibling, &node)
+            } else {
+                merge(&node, &sibling)
+            };
+        }
+        j == self.siblings.len() && node == self.root
+    }
+
+    /// the abi encoded arguments of `verifyProof`, without the selector
+    pub fn encode_args(&self) -> Vec<u8> {
+        let key_len = padded(self.key.len());
+        let value_len = padded(self.value.len());
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.root);
+        buf.extend_from_slice(&word(5 * 32));
+        buf.extend_from_slice(&word(5 * 32 + 32 + key_len));
+        buf.extend_from_slice(&self.bitmap);
+        buf.extend_from_slice(&word(5 * 32 + 32 + key_len + 32 + value_len));
+        put_dynamic(&mut buf, self.key.as_slice());
+        put_dynamic(&mut buf, self.value.as_slice());
+        buf.extend_from_slice(&word(self.siblings.len()));
+        for s in self.siblings.iter() {
+            buf.extend_from_slice(s);
+        }
+        buf
+    }
+
+    /// calldata of a `verifyProof` call
+    pub fn encode_calldata(&self) -> Vec<u8> {
+        [&selector()[..], &self.encode_args()].concat()
+    }
+
+    pub fn decode_calldata(data: &[u8]) -> ZKResult<Self> {
+        if data.len() < 4 || data[..4] != selector() {
+            return Err(ZKError::new(ErrorEnumsStruct::CODEC_INVALID.get_code(), format!("not a call to {}", VERIFY_SIGNATURE)));
+        }
+        let args = &data[4..];
+        let key = read_dynamic(args, read_word(args, 32)?)?;
+        let value = read_dynamic(args, read_word(args, 64)?)?;
+        let siblings_at = read_word(args, 128)?;
+        let count = read_word(args, siblings_at)?;
+        let first = siblings_at.checked_add(32).ok_or_else(abi_invalid)?;
+        let mut siblings = Vec::new();
+        for i in 0..count {
+            let at = i.checked_mul(32).and_then(|o| first.checked_add(o)).ok_or_else(abi_invalid)?;
+            siblings.push(read_bytes32(args, at)?);
+        }
+        Ok(Self { root: read_bytes32(args, 0)?, key, value, bitmap: read_bytes32(args, 96)?, siblings })
+    }
+}
+
+fn padded(len: usize) -> usize {
+    len.div_ceil(32) * 32
+}
+
+fn word(v: usize) -> H256 {
+    let mut ret = ZERO_HASH;
+    ret[24..].copy_from_slice(&(v as u64).to_be_bytes());
+    ret
+}
+
+fn put_dynamic(buf: &mut Vec<u8>, v: &[u8]) {
+    buf.extend_from_slice(&word(v.len()));
+    buf.extend_from_slice(v);
+    buf.resize(buf.len() + padded(v.len()) - v.len(), 0);
+}
+
+fn abi_invalid() -> ZKError {
+    ZKError::new(ErrorEnumsStruct::CODEC_INVALID.get_code(), "malformed abi arguments".to_string())
+}
+
+fn read_bytes32(args: &[u8], at: usize) -> ZKResult<H256> {
+    let end = at.checked_add(32).ok_or_else(abi_invalid)?;
+    let mut ret = ZERO_HASH;
+    ret.copy_from_slice(args.get(at..end).ok_or_else(abi_invalid)?);
+    Ok(ret)
+}
+
+/// a word used as an offset or length, anything past 64 bits can not address the calldata
+fn read_word(args: &[u8], at: usize) -> ZKResult<usize> {
+    let w = read_bytes32(args, at)?;
+    if w[..24] != [0u8; 24] {
+        return Err(abi_invalid());
+    }
+    usize::try_from(u64::from_be_bytes(w[24..].try_into().unwrap())).map_err(|_| abi_invalid())
+}
+
+fn read_dynamic(args: &[u8], at: usize) -> ZKResult<Vec<u8>> {
+    let len = read_word(args, at)?;
+    let start = at.checked_add(32).ok_or_else(abi_invalid)?;
+    let end = start.checked_add(len).ok_or_else(abi_invalid)?;
+    args.get(start..end).map(|v| v.to_vec()).ok_or_else(abi_invalid)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree::hasher::KeccakHasher;
+    use crate::tree::smt::SMTreeDB;
+    use crate::tree::solidity::{selector, SolidityProof};
+    use crate::tree::tree::DB;
+
+    #[test]
+    pub fn test_solidity_proof() {
+        let mut smt = SMTreeDB::<KeccakHasher>::new();
+        for i in 0..8u8 {
+            smt.set(&[i], vec![i; 40]).unwrap();
+        }
+
+        let inclusion = SolidityProof::from_smt(&smt, &[3]).expect("fail to export");
+        assert_eq!(inclusion.value, vec![3; 40]);
+        assert!(inclusion.verify());
+        let exclusion = SolidityProof::from_smt(&smt, &[42]).expect("fail to export");
+        assert!(exclusion.value.is_empty());
+        assert!(exclusion.verify());
+
+        let calldata = inclusion.encode_calldata();
+        assert_eq!(calldata[..4], selector());
+        assert_eq!((calldata.len() - 4) % 32, 0);
+        // root, key offset, value offset, bitmap, siblings offset
+        assert_eq!(calldata[4 + 63], 5 * 32);
+        assert_eq!(SolidityProof::decode_calldata(&calldata).expect("fail to decode"), inclusion);
+        assert!(SolidityProof::decode_calldata(&calldata[..calldata.len() - 1]).is_err());
+
+        let mut forged = inclusion.clone();
+        forged.value = vec![4; 40];
+        assert!(!forged.verify());
+        let mut forged = exclusion.clone();
+        forged.key = vec![3];
+        assert!(!forged.verify());
+        let mut forged = inclusion;
+        forged.siblings.pop();
+        assert!(!forged.verify());
+    }
+
+    #[test]
+    pub fn test_rejects_huge_offsets() {
+        let mut smt = SMTreeDB::<KeccakHasher>::new();
+        smt.set(&[1], vec![1]).unwrap();
+        let calldata = SolidityProof::from_smt(&smt, &[1]).expect("fail to export").encode_calldata();
+        let siblings_at = 4 + u64::from_be_bytes(calldata[4 + 152..4 + 160].try_into().unwrap()) as usize;
+        let near_max = [u64::MAX, u64::MAX - 31, u64::MAX - 32, (usize::MAX / 32) as u64];
+        for word_at in [4 + 32, 4 + 64, 4 + 128, siblings_at] {
+            for v in near_max {
+                let mut forged = calldata.clone();
+                forged[word_at + 24..word_at + 32].copy_from_slice(&v.to_be_bytes());
+                assert!(SolidityProof::decode_calldata(&forged).is_err());
+            }
+        }
+    }
+}
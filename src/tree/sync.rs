@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+use merk::chunks::ChunkProducer;
+use merk::restore::Restorer;
+use merk::tree::NULL_HASH;
+use merk::Merk;
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::tree::codec::{decode_with, header, Codec, TAG_CHUNK_MANIFEST};
+use crate::tree::merkle::MerkleRocksDB;
+use crate::tree::tree::TreeDB;
+
+fn sync_failed(msg: String) -> ZKError {
+    ZKError::new(ErrorEnumsStruct::STATE_SYNC_FAILED.get_code(), msg)
+}
+
+/// what a replica needs before the first chunk arrives: the root every chunk is checked against
+/// and how many chunks make up the state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkManifest {
+    pub root: [u8; 32],
+    pub chunks: usize,
+}
+
+impl Codec for ChunkManifest {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = header(TAG_CHUNK_MANIFEST);
+        buf.extend_from_slice(&self.root);
+        buf.extend_from_slice(&(self.chunks as u64).to_be_bytes());
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> ZKResult<Self> {
+        decode_with(bytes, TAG_CHUNK_MANIFEST, |r| {
+            let mut root = [0u8; 32];
+            root.copy_from_slice(r.take(32)?);
+            let chunks = usize::try_from(r.u64()?).map_err(|_| r.error())?;
+            Ok(ChunkManifest { root, chunks })
+        })
+    }
+}
+
+/// the committed state of a store cut into merk chunk proofs. the store must not be written
+/// while the export is alive, the chunks would no longer match the manifest
+pub struct ChunkExport<'a> {
+    producer: Option<ChunkProducer<'a>>,
+    manifest: ChunkManifest,
+}
+
+impl<'a> ChunkExport<'a> {
+    pub fn new(db: &'a MerkleRocksDB) -> ZKResult<Self> {
+        let root = db.root_hash();
+        if root == NULL_HASH {
+            return Ok(Self { producer: None, manifest: ChunkManifest { root, chunks: 0 } });
+        }
+        let producer = db.merk().chunks().map_err(|e| {
+            sync_failed("fail to produce chunks".to_string()).with_error(Box::new(e))
+        })?;
+        let chunks = producer.len();
+        Ok(Self { producer: Some(producer), manifest: ChunkManifest { root, chunks } })
+    }
+
+    pub fn manifest(&self) -> ChunkManifest {
+        self.manifest
+    }
+
+    pub fn chunk(&mut self, index: usize) -> ZKResult<Vec<u8>> {
+        let producer = match self.producer.as_mut() {
+            Some(p) if index < self.manifest.chunks => p,
+            _ => return Err(sync_failed(format!("chunk {} out of {}", index, self.manifest.chunks))),
+        };
+        producer.chunk(index).map_err(|e| {
+            sync_failed(format!("fail to produce chunk {}", index)).with_error(Box::new(e))
+        })
+    }
+}
+
+/// rebuilds a store at a fresh path from the chunks of a `ChunkExport`, in order.
+/// every chunk is checked against the manifest root before it is written
+pub struct ChunkImport {
+    path: PathBuf,
+    restorer: Option<Restorer>,
+    manifest: ChunkManifest,
+    next: usize,
+}
+
+impl ChunkImport {
+    pub fn new<P: AsRef<Path>>(p: P, manifest: ChunkManifest) -> ZKResult<Self> {
+        let path = p.as_ref().to_path_buf();
+        if path.exists() {
+            return Err(sync_failed(format!("{} already exists", path.display())));
+        }
+        let restorer = match manifest.chunks {
+            0 => None,
+            n => Some(Restorer::new(path.as_path(), manifest.root, n).map_err(|e| {
+                sync_failed("fail to start restore".to_string()).with_error(Box::new(e))
+            })?),
+        };
+        Ok(Self { path, restorer, manifest, next: 0 })
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.manifest.chunks - self.next
+    }
+
+    /// returns how many chunks are still expected, a chunk failing verification is rejected
+    pub fn process(&mut self, chunk: &[u8]) -> ZKResult<usize> {
+        let restorer = match self.restorer.as_mut() {
+            Some(r) if self.next < self.manifest.chunks => r,
+            _ => return Err(sync_failed(format!("all {} chunks already processed", self.manifest.chunks))),
+        };
+        restorer.process_chunk(chunk).map_err(|e| {
+            sync_failed(format!("chunk {} rejected", self.next)).with_error(Box::new(e))
+        })?;
+        self.next += 1;
+        Ok(self.remaining())
+    }
+
+    pub fn finish(self) -> ZKResult<MerkleRocksDB> {
+        if self.remaining() != 0 {
+            return Err(sync_failed(format!("{} chunks missing", self.remaining())));
+        }
+        let merk = match self.restorer {
+            Some(r) => r.finalize().map_err(|e| {
+                sync_failed("fail to finalize restore".to_string()).with_error(Box::new(e))
+            })?,
+            None => Merk::open(self.path.as_path()).map_err(|e| {
+                ZKError::from(ErrorEnumsStruct::MERKLE_OPEN_FAILED).with_error(Box::new(e))
+            })?,
+        };
+        let db = MerkleRocksDB::new(merk);
+        if db.root_hash() != self.manifest.root {
+            return Err(sync_failed(format!("restored root {}, expected {}",
+                                           hex::encode(db.root_hash()), hex::encode(self.manifest.root))));
+        }
+        Ok(db)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree::codec::Codec;
+    use crate::tree::merkle::MerkleRocksDB;
+    use crate::tree::operation::Operation;
+    use crate::tree::sync::{ChunkExport, ChunkImport, ChunkManifest};
+    use crate::tree::tree::{DB, TreeDB};
+
+    fn db_path(name: &str) -> std::path::PathBuf {
+        let p = std::env::temp_dir().join(format!("zkp_sync_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&p);
+        p
+    }
+
+    #[test]
+    pub fn test_manifest_codec() {
+        let manifest = ChunkManifest { root: [7; 32], chunks: 12 };
+        assert_eq!(ChunkManifest::decode(&manifest.encode()).expect("fail to decode"), manifest);
+        assert!(ChunkManifest::decode(&manifest.encode()[..10]).is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_sync() {
+        let mut source = MerkleRocksDB::new_with_path(db_path("source")).expect("fail to open");
+        source.commit((0..200u32).map(|i| Operation::Set(i.to_be_bytes().to_vec(), vec![i as u8; 16])).collect())
+            .expect("fail to commit");
+
+        let mut export = ChunkExport::new(&source).expect("fail to export");
+        let manifest = ChunkManifest::decode(&export.manifest().encode()).expect("fail to decode");
+        let mut import = ChunkImport::new(db_path("replica"), manifest).expect("fail to import");
+        for i in 0..manifest.chunks {
+            import.process(&export.chunk(i).expect("fail to get chunk")).expect("fail to process chunk");
+        }
+        assert!(import.process(&export.chunk(0).unwrap()).is_err());
+        let replica = import.finish().expect("fail to finish");
+        assert_eq!(replica.root_hash(), source.root_hash());
+        assert_eq!(replica.get(&7u32.to_be_bytes()).unwrap(), Some(vec![7; 16].into()));
+
+        let mut forged = export.chunk(0).unwrap();
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        let mut import = ChunkImport::new(db_path("forged"), manifest).expect("fail to import");
+        assert!(import.process(&forged).is_err());
+    }
+}
@@ -1,4 +1,6 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ops::Bound;
 use std::path::Path;
 
 use hash256_std_hasher::Hash256StdHasher;
@@ -11,16 +13,97 @@ extern crate merk;
 use merk::*;
 use merk::proofs::Query;
 use merk::proofs::query::Map;
-use crate::error::{ErrorEnums, ZKResult};
+use crate::error::{ErrorEnums, ErrorEnumsStruct, ZKError, ZKResult};
 use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
-use crate::tree::operation::Operation;
+use crate::tree::operation::{resolve, Operation, Precondition};
 
 
+/// called by `DB::scan` for every key/value pair, an error stops the scan
+pub type Visitor<'a> = dyn FnMut(&[u8], &[u8]) -> ZKResult<()> + 'a;
+
+/// root of an empty tree, the same on every backend
+pub const EMPTY_ROOT: [u8; 32] = [0; 32];
+
 pub trait DB {
-    fn get(&self, k: Vec<u8>) -> ZKResult<Option<Vec<u8>>>;
-    fn set(&mut self, k: Vec<u8>, v: Vec<u8>) -> ZKResult<Vec<u8>>;
+    /// layers keeping the value in memory hand it out borrowed, everything else returns it owned
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>>;
+    /// returns the value `k` held before the write
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>>;
     // fn batch_operation(&mut self, ops: Vec<Operation>) -> Result<()>;
-    fn delete(&mut self, k: Vec<u8>) -> ZKResult<()>;
+    /// returns the removed value
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>>;
+
+    /// values of `keys` in the same order, backends able to batch lookups should override it
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|k| self.get(k).map(|v| v.map(Cow::into_owned))).collect()
+    }
+
+    /// every key/value pair in `[start, end)`, in key order. an empty `end` reads to the last key
+    fn range(&self, _start: &[u8], _end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        Err(ZKError::new(ErrorEnumsStruct::OPERATION_INVALID.get_code(), "range reads are not supported".to_string()))
+    }
+
+    /// visits every key/value pair in `[start, end)` in key order without collecting them, stops at
+    /// the first error of `f`. backends able to iterate in place should override it
+    fn scan(&self, start: &[u8], end: &[u8], f: &mut Visitor) -> ZKResult<()> {
+        for (k, v) in self.range(start, end)? {
+            f(k.as_slice(), v.as_slice())?;
+        }
+        Ok(())
+    }
+
+    /// resolves `operations` against this layer and writes the result through `set` and `delete`
+    fn write_batch(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        for op in resolve(self, operations)? {
+            match op {
+                Operation::Set(k, v) => self.set(k.as_slice(), v).map(|_| ())?,
+                Operation::Delete(k) => self.delete(k.as_slice()).map(|_| ())?,
+                _ => unreachable!("resolve only emits sets and deletes"),
+            }
+        }
+        Ok(())
+    }
+
+    /// writes `new` only if `k` currently holds `expected`, `None` meaning absent on both sides.
+    /// layers sharing their state behind a lock must override it to check and write under one lock
+    fn compare_and_set(&mut self, k: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> ZKResult<()> {
+        let current = self.get(k)?;
+        if current.as_deref() != expected {
+            return Err(precondition_failed(k));
+        }
+        drop(current);
+        match new {
+            Some(v) => self.set(k, v).map(|_| ()),
+            None => self.delete(k).map(|_| ()),
+        }
+    }
+}
+
+/// `[start, end)` as map bounds, an empty `end` leaves the range open
+pub(crate) fn range_bounds<'a>(start: &'a [u8], end: &'a [u8]) -> Option<(Bound<&'a [u8]>, Bound<&'a [u8]>)> {
+    if end.is_empty() {
+        return Some((Bound::Included(start), Bound::Unbounded));
+    }
+    if start >= end {
+        return None;
+    }
+    Some((Bound::Included(start), Bound::Excluded(end)))
+}
+
+/// the first key past every key starting with `prefix`, `None` if there is none
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+pub(crate) fn precondition_failed(k: &[u8]) -> ZKError {
+    ZKError::new(ErrorEnumsStruct::PRECONDITION_FAILED.get_code(), format!("key {} changed", hex::encode(k)))
 }
 
 pub trait TreeDB: DB {
@@ -28,6 +111,81 @@ pub trait TreeDB: DB {
     fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse>;
     fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()>;
     fn root_hash(&self) -> [u8; 32];
+
+    /// drains everything buffered above the backing store and returns the full list of
+    /// operations a following `commit` would apply, without applying them
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        Ok(operations)
+    }
+
+    /// commits only if every precondition holds, nothing is applied otherwise.
+    /// conditions see writes still buffered in the layers above the store
+    fn commit_if(&mut self, conditions: Vec<Precondition>, operations: Vec<Operation>) -> ZKResult<()> {
+        for c in conditions.iter() {
+            if self.get(c.key.as_slice())?.as_deref() != c.expected.as_deref() {
+                return Err(precondition_failed(c.key.as_slice()));
+            }
+        }
+        self.commit(operations)
+    }
 }
 
+impl<T: DB + ?Sized> DB for Box<T> {
+    fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+        (**self).get(k)
+    }
 
+    fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+        (**self).set(k, v)
+    }
+
+    fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+        (**self).delete(k)
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> ZKResult<Vec<Option<Vec<u8>>>> {
+        (**self).get_many(keys)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> ZKResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        (**self).range(start, end)
+    }
+
+    fn scan(&self, start: &[u8], end: &[u8], f: &mut Visitor) -> ZKResult<()> {
+        (**self).scan(start, end, f)
+    }
+
+    fn write_batch(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        (**self).write_batch(operations)
+    }
+
+    fn compare_and_set(&mut self, k: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> ZKResult<()> {
+        (**self).compare_and_set(k, expected, new)
+    }
+}
+
+impl<T: TreeDB + ?Sized> TreeDB for Box<T> {
+    fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+        (**self).prove(req)
+    }
+
+    fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+        (**self).verify(req)
+    }
+
+    fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+        (**self).commit(operations)
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        (**self).root_hash()
+    }
+
+    fn prepare(&mut self, operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+        (**self).prepare(operations)
+    }
+
+    fn commit_if(&mut self, conditions: Vec<Precondition>, operations: Vec<Operation>) -> ZKResult<()> {
+        (**self).commit_if(conditions, operations)
+    }
+}
@@ -0,0 +1,416 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tiny_keccak::{Hasher, Keccak};
+use crate::error::{ErrorEnumsStruct, ZKError, ZKResult};
+use crate::tree::codec::{put_bytes, put_operations, Reader};
+use crate::tree::operation::{resolve, Operation};
+use crate::tree::tree::TreeDB;
+
+const RECORD_PREPARE: u8 = 1;
+const RECORD_COMMIT: u8 = 2;
+
+const CHECKSUM_LEN: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum WalRecord {
+    /// all operations of one block, for every tree taking part in the commit
+    Prepare { height: u64, trees: Vec<(String, Vec<Operation>)> },
+    /// the block is decided, every tree must reflect the prepared operations
+    Commit { height: u64 },
+}
+
+/// append-only log file, every record is framed as `len(u32) | body | checksum`,
+/// a torn write at the tail is treated as the end of the log
+pub struct WriteAheadLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl WriteAheadLog {
+    pub fn open<P: AsRef<Path>>(p: P) -> ZKResult<Self> {
+        let path = p.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).read(true).append(true).open(&path).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e))
+        })?;
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    pub fn is_empty(&self) -> ZKResult<bool> {
+        self.file.metadata().map(|m| m.len() == 0).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e))
+        })
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> ZKResult<()> {
+        let body = encode_record(record);
+        let mut frame = Vec::with_capacity(body.len() + 4 + CHECKSUM_LEN);
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.extend_from_slice(body.as_slice());
+        frame.extend_from_slice(&checksum(body.as_slice()));
+        self.file.write_all(frame.as_slice()).and_then(|_| {
+            self.file.sync_data()
+        }).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e))
+        })
+    }
+
+    pub fn records(&self) -> ZKResult<Vec<WalRecord>> {
+        let mut data = Vec::new();
+        File::open(&self.path).and_then(|mut f| {
+            f.read_to_end(&mut data)
+        }).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e))
+        })?;
+
+        let mut ret = Vec::new();
+        let mut offset = 0;
+        while offset + 4 <= data.len() {
+            let len = u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as usize;
+            let end = offset + 4 + len + CHECKSUM_LEN;
+            if end > data.len() {
+                break;
+            }
+            let body = &data[offset + 4..offset + 4 + len];
+            if checksum(body) != data[offset + 4 + len..end] {
+                break;
+            }
+            ret.push(decode_record(body)?);
+            offset = end;
+        }
+        Ok(ret)
+    }
+
+    /// drops every record, called once all trees reflect the last committed block
+    pub fn truncate(&mut self) -> ZKResult<()> {
+        self.file.set_len(0).and_then(|_| {
+            self.file.sync_all()
+        }).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::IO_ERROR).with_error(Box::new(e))
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RecoveryOutcome {
+    /// the log was empty
+    Clean,
+    /// the block was committed but not applied everywhere, it has been replayed to every tree
+    Replayed(u64),
+    /// the block never reached the commit marker, nothing was applied and it has been dropped
+    Discarded(u64),
+}
+
+/// two phase commit across several trees:
+/// the operations of every tree are written to the log and then marked committed,
+/// only afterwards they are applied to the trees one by one.
+/// a crash in between is repaired by `recover`. operations are resolved to `Set` and `Delete`
+/// before they are logged, those are idempotent so replaying to a tree which already applied
+/// the block is harmless, where an `Increment` would be applied twice
+pub struct AtomicCommit {
+    wal: WriteAheadLog,
+}
+
+impl AtomicCommit {
+    pub fn new(wal: WriteAheadLog) -> Self {
+        Self { wal }
+    }
+
+    pub fn open<P: AsRef<Path>>(p: P) -> ZKResult<Self> {
+        WriteAheadLog::open(p).map(Self::new)
+    }
+
+    pub fn commit(&mut self, height: u64, trees: &mut [(&str, &mut dyn TreeDB)]) -> ZKResult<()> {
+        self.commit_with(height, trees, vec![])
+    }
+
+    /// like `commit`, with `operations[i]` applied to `trees[i]` on top of what that tree has
+    /// buffered. the operations only reach the trees as part of the block, a failure leaves no
+    /// trace of them in the stacks
+    pub fn commit_with(&mut self, height: u64, trees: &mut [(&str, &mut dyn TreeDB)], operations: Vec<Vec<Operation>>) -> ZKResult<()> {
+        if !self.wal.is_empty()? {
+            return Err(ZKError::from(ErrorEnumsStruct::WAL_NOT_RECOVERED));
+        }
+        if operations.len() > trees.len() {
+            return Err(ZKError::new(ErrorEnumsStruct::OPERATION_INVALID.get_code(),
+                                    format!("operations for {} trees, got {}", operations.len(), trees.len())));
+        }
+
+        let mut drained = Vec::with_capacity(trees.len());
+        let prepared = match self.log_block(height, trees, operations, &mut drained) {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                // nothing was applied, the writes drained so far go back to their trees
+                let _ = self.wal.truncate();
+                for ((_, tree), ops) in trees.iter_mut().zip(drained) {
+                    if let Err(restore) = tree.write_batch(ops) {
+                        return Err(e.with_wrapped_error(Box::new(restore)));
+                    }
+                }
+                return Err(e);
+            }
+        };
+
+        self.apply(trees, prepared).map_err(|e| {
+            ZKError::from(ErrorEnumsStruct::ATOMIC_COMMIT_FAILED).with_wrapped_error(Box::new(e))
+        })?;
+        self.wal.truncate()
+    }
+
+    /// drains every tree and logs the block as committed, `drained` collects what each tree gave up.
+    /// the extra operations of a tree go through its `prepare` once the tree is drained, so they
+    /// are checked by its layers without being buffered in them
+    fn log_block(&mut self, height: u64, trees: &mut [(&str, &mut dyn TreeDB)], mut operations: Vec<Vec<Operation>>,
+                 drained: &mut Vec<Vec<Operation>>) -> ZKResult<Vec<(String, Vec<Operation>)>> {
+        operations.resize(trees.len(), vec![]);
+        let mut prepared = Vec::with_capacity(trees.len());
+        for ((name, tree), extra) in trees.iter_mut().zip(operations) {
+            let mut ops = tree.prepare(vec![])?;
+            drained.push(ops.clone());
+            if !extra.is_empty() {
+                ops = tree.prepare([ops, extra].concat())?;
+            }
+            prepared.push((name.to_string(), resolve(&**tree, ops)?));
+        }
+        self.wal.append(&WalRecord::Prepare { height, trees: prepared.clone() })?;
+        self.wal.append(&WalRecord::Commit { height })?;
+        Ok(prepared)
+    }
+
+    pub fn recover(&mut self, trees: &mut [(&str, &mut dyn TreeDB)]) -> ZKResult<RecoveryOutcome> {
+        let mut prepared = None;
+        let mut committed = false;
+        for record in self.wal.records()? {
+            match record {
+                WalRecord::Prepare { height, trees } => {
+                    prepared = Some((height, trees));
+                    committed = false;
+                }
+                WalRecord::Commit { height } => {
+                    committed = prepared.as_ref().is_some_and(|(h, _)| *h == height);
+                }
+            }
+        }
+
+        let outcome = match prepared {
+            None => RecoveryOutcome::Clean,
+            Some((height, _)) if !committed => RecoveryOutcome::Discarded(height),
+            Some((height, ops)) => {
+                self.apply(trees, ops)?;
+                RecoveryOutcome::Replayed(height)
+            }
+        };
+        self.wal.truncate()?;
+        Ok(outcome)
+    }
+
+    fn apply(&self, trees: &mut [(&str, &mut dyn TreeDB)], prepared: Vec<(String, Vec<Operation>)>) -> ZKResult<()> {
+        for (name, ops) in prepared {
+            let (_, tree) = trees.iter_mut().find(|(n, _)| *n == name.as_str()).ok_or_else(|| {
+                ZKError::new(ErrorEnumsStruct::WAL_TREE_NOT_FOUND.get_code(), format!("unknown tree {}", name))
+            })?;
+            tree.commit(ops)?;
+        }
+        Ok(())
+    }
+}
+
+fn checksum(body: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut keccak = Keccak::v256();
+    keccak.update(body);
+    let mut out = [0u8; 32];
+    keccak.finalize(&mut out);
+    let mut ret = [0u8; CHECKSUM_LEN];
+    ret.copy_from_slice(&out[..CHECKSUM_LEN]);
+    ret
+}
+
+fn encode_record(record: &WalRecord) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match record {
+        WalRecord::Prepare { height, trees } => {
+            buf.push(RECORD_PREPARE);
+            buf.extend_from_slice(&height.to_be_bytes());
+            buf.extend_from_slice(&(trees.len() as u32).to_be_bytes());
+            for (name, ops) in trees {
+                put_bytes(&mut buf, name.as_bytes());
+                put_operations(&mut buf, ops);
+            }
+        }
+        WalRecord::Commit { height } => {
+            buf.push(RECORD_COMMIT);
+            buf.extend_from_slice(&height.to_be_bytes());
+        }
+    }
+    buf
+}
+
+fn decode_record(body: &[u8]) -> ZKResult<WalRecord> {
+    let mut r = Reader::new(body, ErrorEnumsStruct::WAL_CORRUPTED);
+    match r.u8()? {
+        RECORD_PREPARE => {
+            let height = r.u64()?;
+            let tree_count = r.u32()?;
+            let mut trees = Vec::new();
+            for _ in 0..tree_count {
+                let name = String::from_utf8(r.bytes()?).map_err(|e| {
+                    ZKError::from(ErrorEnumsStruct::WAL_CORRUPTED).with_error(Box::new(e))
+                })?;
+                trees.push((name, r.operations()?));
+            }
+            Ok(WalRecord::Prepare { height, trees })
+        }
+        RECORD_COMMIT => Ok(WalRecord::Commit { height: r.u64()? }),
+        _ => Err(ZKError::from(ErrorEnumsStruct::WAL_CORRUPTED)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use crate::error::ZKResult;
+    use crate::middleware::cache::CacheMiddleware;
+    use crate::middleware::middleware::DBMiddleware;
+    use crate::tree::couple::{ProveRequest, ProveResponse, VerifyRequest, VerifyResponse};
+    use crate::tree::memory::MemoryTreeDB;
+    use crate::tree::operation::{encode_counter, Operation};
+    use crate::tree::tree::{DB, TreeDB};
+    use crate::tree::wal::{AtomicCommit, RecoveryOutcome, WalRecord, WriteAheadLog};
+
+    /// a memory tree with a write buffer and switches to fail `prepare` or `commit`
+    #[derive(Default)]
+    struct FlakyDB {
+        db: MemoryTreeDB,
+        pending: Vec<Operation>,
+        fail_prepare: bool,
+        fail_commit: bool,
+    }
+
+    impl DB for FlakyDB {
+        fn get(&self, k: &[u8]) -> ZKResult<Option<Cow<'_, [u8]>>> {
+            self.db.get(k)
+        }
+
+        fn set(&mut self, k: &[u8], v: Vec<u8>) -> ZKResult<Option<Vec<u8>>> {
+            self.db.set(k, v)
+        }
+
+        fn delete(&mut self, k: &[u8]) -> ZKResult<Option<Vec<u8>>> {
+            self.db.delete(k)
+        }
+
+        fn write_batch(&mut self, mut operations: Vec<Operation>) -> ZKResult<()> {
+            self.pending.append(&mut operations);
+            Ok(())
+        }
+    }
+
+    impl TreeDB for FlakyDB {
+        fn prove(&self, req: ProveRequest) -> ZKResult<ProveResponse> {
+            self.db.prove(req)
+        }
+
+        fn verify(&self, req: VerifyRequest) -> ZKResult<VerifyResponse> {
+            self.db.verify(req)
+        }
+
+        fn commit(&mut self, operations: Vec<Operation>) -> ZKResult<()> {
+            if self.fail_commit {
+                return Err("commit failed".into());
+            }
+            self.db.commit(operations)
+        }
+
+        fn root_hash(&self) -> [u8; 32] {
+            self.db.root_hash()
+        }
+
+        fn prepare(&mut self, mut operations: Vec<Operation>) -> ZKResult<Vec<Operation>> {
+            if self.fail_prepare {
+                return Err("prepare failed".into());
+            }
+            let mut ops = std::mem::take(&mut self.pending);
+            ops.append(&mut operations);
+            Ok(ops)
+        }
+    }
+
+    fn wal_path(name: &str) -> std::path::PathBuf {
+        let p = std::env::temp_dir().join(format!("zkp_wal_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&p);
+        p
+    }
+
+    #[test]
+    pub fn test_commit_truncates_log() {
+        let path = wal_path("commit");
+        let mut acc = FlakyDB::default();
+        let mut order = FlakyDB::default();
+        let mut committer = AtomicCommit::open(&path).expect("fail to open");
+        committer.commit(1, &mut [("acc", &mut acc), ("order", &mut order)]).expect("fail to commit");
+        assert!(WriteAheadLog::open(&path).unwrap().is_empty().unwrap());
+    }
+
+    #[test]
+    pub fn test_failed_prepare_keeps_buffered_writes() {
+        let path = wal_path("prepare");
+        let mut acc = CacheMiddleware::new(DBMiddleware::new(MemoryTreeDB::new()));
+        let mut order = FlakyDB { fail_prepare: true, ..Default::default() };
+        let empty = acc.root_hash();
+        acc.set(&[1], vec![1]).expect("fail to set");
+        acc.write_batch(vec![Operation::Increment(vec![5], 5)]).expect("fail to write");
+
+        let mut committer = AtomicCommit::open(&path).expect("fail to open");
+        assert!(committer.commit(1, &mut [("acc", &mut acc), ("order", &mut order)]).is_err());
+        assert!(WriteAheadLog::open(&path).unwrap().is_empty().unwrap());
+        assert_eq!(acc.root_hash(), empty);
+        assert_eq!(acc.get(&[1]).unwrap(), Some(vec![1].into()));
+        assert_eq!(acc.get(&[5]).unwrap(), Some(encode_counter(5).into()));
+
+        order.fail_prepare = false;
+        committer.commit(1, &mut [("acc", &mut acc), ("order", &mut order)]).expect("fail to commit");
+        assert_ne!(acc.root_hash(), empty);
+        assert_eq!(acc.get(&[5]).unwrap(), Some(encode_counter(5).into()));
+    }
+
+    #[test]
+    pub fn test_recover_replays_committed_block() {
+        let path = wal_path("replay");
+        let mut acc = FlakyDB::default();
+        let mut order = FlakyDB { fail_commit: true, ..Default::default() };
+        acc.db.set(&[5], encode_counter(10)).unwrap();
+        acc.pending.push(Operation::Set(vec![1], vec![2]));
+        acc.pending.push(Operation::Increment(vec![5], 5));
+        order.pending.push(Operation::Set(vec![3], vec![4]));
+
+        let mut committer = AtomicCommit::open(&path).expect("fail to open");
+        assert!(committer.commit(7, &mut [("acc", &mut acc), ("order", &mut order)]).is_err());
+        assert_eq!(acc.get(&[1]).unwrap(), Some(vec![2].into()));
+        assert!(order.db.is_empty());
+
+        order.fail_commit = false;
+        let outcome = committer.recover(&mut [("acc", &mut acc), ("order", &mut order)]).expect("fail to recover");
+        assert_eq!(outcome, RecoveryOutcome::Replayed(7));
+        assert_eq!(acc.get(&[1]).unwrap(), Some(vec![2].into()));
+        assert_eq!(acc.get(&[5]).unwrap(), Some(encode_counter(15).into()));
+        assert_eq!(order.get(&[3]).unwrap(), Some(vec![4].into()));
+    }
+
+    #[test]
+    pub fn test_recover_discards_uncommitted_block() {
+        let path = wal_path("discard");
+        let mut acc = FlakyDB::default();
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(&WalRecord::Prepare { height: 3, trees: vec![("acc".to_string(), vec![Operation::Set(vec![1], vec![1])])] }).unwrap();
+
+        let mut committer = AtomicCommit::new(wal);
+        let outcome = committer.recover(&mut [("acc", &mut acc)]).expect("fail to recover");
+        assert_eq!(outcome, RecoveryOutcome::Discarded(3));
+        assert!(acc.db.is_empty());
+        assert_eq!(committer.recover(&mut [("acc", &mut acc)]).unwrap(), RecoveryOutcome::Clean);
+    }
+}